@@ -1,5 +1,5 @@
-import { TICKS_PER_BEAT, Event } from './murmel-std'
-import { Note } from './tonal/tonal.js'
+import { TICKS_PER_BEAT, Event } from 'murmel:std'
+import { Note } from 'tonal'
 
 const note = function* (note: number, beats: number): Generator<Event> {
     yield { type: 'NoteOn', note }
@@ -9,16 +9,16 @@ const note = function* (note: number, beats: number): Generator<Event> {
 
 const generator = function* (): Generator<Event> {
     let base = Note.midi('C1')!
-    let bpm = 120
+
+    yield { type: 'ChangeBpm', bpm: 120 }
+    yield { type: 'TempoRamp', toBpm: 180, overTicks: 64 * TICKS_PER_BEAT, curve: 'linear' }
 
     while (true) {
         yield { type: 'Marker' }
-        yield { type: 'ChangeBpm', bpm }
         yield* note(base, 0.25)
         yield* note(base, 0.25)
         yield* note(base, 0.25)
         yield* note(base, 0.25)
-        bpm++
     }
 }
 
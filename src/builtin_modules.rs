@@ -0,0 +1,180 @@
+use deno_core::ModuleSpecifier;
+
+pub const BUILTIN_SCHEME: &str = "murmel";
+
+// modules compiled into the binary, importable as e.g. `murmel:std`.
+pub const BUILTIN_MODULES: &[(&str, &str)] = &[("murmel:std", include_str!("std/murmel-std.ts"))];
+
+/// TypeScript source of a built-in module, or an error listing the available
+/// ones.
+pub fn builtin_module(specifier: &ModuleSpecifier) -> anyhow::Result<&'static str> {
+    BUILTIN_MODULES
+        .iter()
+        .find(|(name, _)| *name == specifier.as_str())
+        .map(|(_, source)| *source)
+        .ok_or_else(|| {
+            let known: Vec<&str> = BUILTIN_MODULES.iter().map(|(name, _)| *name).collect();
+            anyhow::anyhow!(
+                "Unknown built-in module {}, expected one of {}",
+                specifier,
+                known.join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use deno_ast::{
+        swc::ast::{
+            Decl, Expr, ModuleDecl, ModuleItem, TsKeywordTypeKind, TsLit, TsLitType, TsType,
+            TsTypeElement, TsUnionOrIntersectionType,
+        },
+        MediaType, ParseParams, SourceTextInfo,
+    };
+    use deno_core::serde_json::{self, Map, Value};
+
+    // `variant_name` and `VARIANTS` from a single list. The match is
+    // exhaustive, so a new variant fails to compile here, as a reminder to
+    // add it to the list and to the std `Event` type.
+    macro_rules! event_variants {
+        ($($variant:ident),* $(,)?) => {
+            fn variant_name(event: &Event) -> &'static str {
+                match event {
+                    $(Event::$variant { .. } => stringify!($variant),)*
+                }
+            }
+
+            const VARIANTS: &[&str] = &[$(stringify!($variant)),*];
+        };
+    }
+
+    event_variants!(
+        NoteOn,
+        NoteOff,
+        Wait,
+        AllNotesOff,
+        ChangeBpm,
+        TempoRamp,
+        TimeSignature,
+        Print,
+        Marker,
+    );
+
+    fn literal_example(literal: &TsLitType) -> Value {
+        match &literal.lit {
+            TsLit::Str(s) => Value::from(s.value.to_string()),
+            other => panic!("unexpected literal type: {:?}", other),
+        }
+    }
+
+    // an example value for every member of the std `Event` union, built from
+    // the declared property types.
+    fn std_event_examples() -> Vec<Value> {
+        let specifier = ModuleSpecifier::parse("murmel:std").unwrap();
+        let parsed = deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            text_info: SourceTextInfo::from_string(builtin_module(&specifier).unwrap().to_string()),
+            media_type: MediaType::TypeScript,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })
+        .unwrap();
+
+        let event_type = parsed
+            .module()
+            .body
+            .iter()
+            .find_map(|item| match item {
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => match &export.decl {
+                    Decl::TsTypeAlias(alias) if &*alias.id.sym == "Event" => {
+                        Some(alias.type_ann.clone())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .expect("murmel:std exports an Event type");
+
+        let members = match *event_type {
+            TsType::TsUnionOrIntersectionType(TsUnionOrIntersectionType::TsUnionType(union)) => {
+                union.types
+            }
+            other => panic!("Event is not a union: {:?}", other),
+        };
+
+        members
+            .iter()
+            .map(|member| {
+                let literal = match &**member {
+                    TsType::TsTypeLit(literal) => literal,
+                    other => panic!("Event member is not an object type: {:?}", other),
+                };
+
+                let mut example = Map::new();
+                for element in literal.members.iter() {
+                    let property = match element {
+                        TsTypeElement::TsPropertySignature(property) => property,
+                        other => panic!("unexpected Event member element: {:?}", other),
+                    };
+                    let key = match &*property.key {
+                        Expr::Ident(ident) => ident.sym.to_string(),
+                        other => panic!("unexpected property key: {:?}", other),
+                    };
+                    let value = match &*property.type_ann.as_ref().unwrap().type_ann {
+                        TsType::TsLitType(literal) => literal_example(literal),
+                        // a union of literals, any of them will do.
+                        TsType::TsUnionOrIntersectionType(
+                            TsUnionOrIntersectionType::TsUnionType(union),
+                        ) => match &*union.types[0] {
+                            TsType::TsLitType(literal) => literal_example(literal),
+                            other => panic!("unexpected union member type: {:?}", other),
+                        },
+                        TsType::TsKeywordType(keyword) => match keyword.kind {
+                            TsKeywordTypeKind::TsNumberKeyword => Value::from(1),
+                            TsKeywordTypeKind::TsStringKeyword => Value::from("text"),
+                            other => panic!("unexpected keyword type: {:?}", other),
+                        },
+                        other => panic!("unexpected property type: {:?}", other),
+                    };
+                    example.insert(key, value);
+                }
+
+                Value::Object(example)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn std_events_deserialize_to_rust_events() {
+        for example in std_event_examples() {
+            let event: Event = serde_json::from_value(example.clone())
+                .unwrap_or_else(|e| panic!("{} is not a valid Event: {}", example, e));
+            assert_eq!(variant_name(&event), example["type"]);
+        }
+    }
+
+    #[test]
+    fn std_events_cover_every_rust_event() {
+        let mut std_names: Vec<String> = std_event_examples()
+            .iter()
+            .map(|example| example["type"].as_str().unwrap().to_string())
+            .collect();
+        std_names.sort();
+
+        let mut rust_names: Vec<&str> = VARIANTS.to_vec();
+        rust_names.sort();
+
+        assert_eq!(std_names, rust_names);
+    }
+
+    #[test]
+    fn std_events_pass_the_event_schema() {
+        for example in std_event_examples() {
+            crate::event_schema::validate_event(&example)
+                .unwrap_or_else(|e| panic!("{} fails the event schema: {}", example, e));
+        }
+    }
+}
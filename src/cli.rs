@@ -0,0 +1,116 @@
+use crate::{event_coordinator::ReloadMode, groove::Groove, snapshot::CREATE_SNAPSHOT_ARG};
+use anyhow::{anyhow, bail};
+use std::path::{Path, PathBuf};
+
+pub enum CliCommand {
+    Run,
+    /// Print or write the TypeScript declarations, see `type_declarations`.
+    Types {
+        output: Option<PathBuf>,
+    },
+}
+
+pub struct CliArgs {
+    pub command: CliCommand,
+    pub record: bool,
+    pub headless: bool,
+    pub osc_port: Option<u16>,
+    pub strict: bool,
+    pub seed: Option<u64>,
+    pub reload_mode: ReloadMode,
+    pub groove: Option<Groove>,
+    pub transpile_cache_dir: Option<PathBuf>,
+    pub no_snapshot: bool,
+    /// Internal, used by the child process that creates the runtime
+    /// snapshot: where to write it, and the scripts to evaluate into it.
+    pub create_snapshot: Option<(PathBuf, PathBuf)>,
+}
+
+impl CliArgs {
+    pub fn parse() -> anyhow::Result<CliArgs> {
+        let mut cli_args = CliArgs {
+            command: CliCommand::Run,
+            record: false,
+            headless: false,
+            osc_port: None,
+            strict: false,
+            seed: None,
+            reload_mode: ReloadMode::Marker,
+            groove: None,
+            transpile_cache_dir: None,
+            no_snapshot: false,
+            create_snapshot: None,
+        };
+
+        let mut args = std::env::args().skip(1).peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "types" => {
+                    // a flag after `types` is not the output path.
+                    let output = args.next_if(|arg| !arg.starts_with("--"));
+                    cli_args.command = CliCommand::Types {
+                        output: output.map(PathBuf::from),
+                    }
+                }
+                "--record" => cli_args.record = true,
+                "--headless" => cli_args.headless = true,
+                "--strict" => cli_args.strict = true,
+                "--no-snapshot" => cli_args.no_snapshot = true,
+                "--seed" => {
+                    let seed = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--seed requires a number"))?;
+                    cli_args.seed = Some(seed.parse()?);
+                }
+                "--reload-at" => {
+                    let mode = args.next().ok_or_else(|| {
+                        anyhow!("--reload-at requires marker, now, beat, bar or <n>bars")
+                    })?;
+                    cli_args.reload_mode = mode.parse()?;
+                }
+                "--swing" => {
+                    let swing = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--swing requires <percent>[@<subdivision>]"))?;
+                    if cli_args.groove.is_some() {
+                        bail!("Only one of --swing and --groove can be given");
+                    }
+                    cli_args.groove = Some(Groove::parse_swing(&swing)?);
+                }
+                "--groove" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--groove requires a groove template file"))?;
+                    if cli_args.groove.is_some() {
+                        bail!("Only one of --swing and --groove can be given");
+                    }
+                    cli_args.groove = Some(Groove::load(Path::new(&path))?);
+                }
+                "--osc-port" => {
+                    let port = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--osc-port requires a port number"))?;
+                    cli_args.osc_port = Some(port.parse()?);
+                }
+                "--transpile-cache" => {
+                    let dir = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--transpile-cache requires a directory"))?;
+                    cli_args.transpile_cache_dir = Some(PathBuf::from(dir));
+                }
+                CREATE_SNAPSHOT_ARG => {
+                    let (path, scripts_path) = args
+                        .next()
+                        .zip(args.next())
+                        .ok_or_else(|| anyhow!("{} requires two paths", CREATE_SNAPSHOT_ARG))?;
+                    cli_args.create_snapshot =
+                        Some((PathBuf::from(path), PathBuf::from(scripts_path)));
+                }
+                _ => bail!("Unknown argument {}", arg),
+            }
+        }
+
+        Ok(cli_args)
+    }
+}
@@ -40,7 +40,7 @@ impl CrosstermRawLogger {
 
 impl Log for CrosstermRawLogger {
     fn enabled(&self, _metadata: &Metadata) -> bool {
-        return true;
+        true
     }
 
     fn log(&self, record: &Record) {
@@ -79,11 +79,11 @@ fn level_to_color(level: Level) -> Color {
 }
 
 pub trait LogErr {
-    fn log_err(&self) -> ();
+    fn log_err(&self);
 }
 
 impl LogErr for Result<(), anyhow::Error> {
-    fn log_err(&self) -> () {
+    fn log_err(&self) {
         match self {
             Err(e) => {
                 warn!("{:?}", e)
@@ -2,16 +2,44 @@ use deno_core::serde::Deserialize;
 
 pub type Ticks = u32;
 pub type NoteValue = u8;
-pub type Bpm = u16;
+/// Beats per minute, fractional values are fine.
+pub type Bpm = f64;
+
+pub const MIN_BPM: Bpm = 1.0;
+pub const MAX_BPM: Bpm = u16::MAX as Bpm;
 
 // some highly composable number.
 pub const TICKS_PER_BEAT: Ticks = 55440;
 
+// channel mode messages, sent as control changes.
+pub const ALL_SOUND_OFF_CONTROLLER: u8 = 120;
+pub const ALL_NOTES_OFF_CONTROLLER: u8 = 123;
+
 #[derive(Deserialize, Debug)]
 pub struct ChangeBpm {
     pub bpm: Bpm,
 }
 
+/// How the tempo moves from the current BPM to `to_bpm`. A linear ramp
+/// changes by the same BPM every tick, an exponential one by the same ratio,
+/// which sounds more even over large changes.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RampCurve {
+    Linear,
+    Exponential,
+}
+
+/// Changes the tempo gradually over `over_ticks`, starting at the current
+/// BPM. Another tempo change or ramp ends it early.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct TempoRamp {
+    pub to_bpm: Bpm,
+    pub over_ticks: Ticks,
+    pub curve: RampCurve,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct NoteOn {
     pub note: NoteValue,
@@ -39,7 +67,7 @@ pub struct AllNotesOff {}
 
 impl AllNotesOff {
     pub fn to_midi_msg(&self) -> [u8; 3] {
-        [0xB0, 0, 0]
+        [0xB0, ALL_NOTES_OFF_CONTROLLER, 0]
     }
 }
 
@@ -48,6 +76,36 @@ pub struct Wait {
     pub ticks: Ticks,
 }
 
+/// `denominator` is the note value of a beat, e.g. 8 for eighth notes.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+impl TimeSignature {
+    /// Longer beats than a whole note or shorter than a 64th are not
+    /// supported.
+    pub const MAX_DENOMINATOR: u8 = 64;
+
+    /// Whether bars can be counted in it, which needs a power of two
+    /// denominator.
+    pub fn is_valid(&self) -> bool {
+        self.numerator > 0
+            && self.denominator.is_power_of_two()
+            && self.denominator <= TimeSignature::MAX_DENOMINATOR
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        TimeSignature {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Event {
@@ -56,6 +114,8 @@ pub enum Event {
     Wait(Wait),
     AllNotesOff(AllNotesOff),
     ChangeBpm(ChangeBpm),
+    TempoRamp(TempoRamp),
+    TimeSignature(TimeSignature),
     Print { value: String },
     Marker,
 }
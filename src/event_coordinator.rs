@@ -1,16 +1,17 @@
 use crate::{
     crossterm_raw_logger::LogErr,
-    event::Event,
-    event_generator::RequestNotesParams,
+    event::{Event, NoteOff, NoteValue, Ticks, Wait},
+    event_generator::{EventGeneratorConfig, RequestNotesParams},
     event_generator_thread::{new_event_generator_actor, EventGeneratorActorHandle},
     player::PlayerEventSource,
+    position::Position,
 };
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use crossbeam::channel::{bounded, unbounded, Receiver, RecvError, Sender};
-use log::{debug, warn};
+use log::{debug, info, warn};
 use std::{
-    collections::VecDeque,
-    path::{Path, PathBuf},
+    collections::{BTreeSet, VecDeque},
+    str::FromStr,
     sync::{Arc, Mutex},
     thread::{spawn, JoinHandle},
 };
@@ -18,10 +19,159 @@ use std::{
 const REQUEST_MORE_WHEN_COUNT_UNDER: usize = 100;
 const REQUEST_PARAMS: RequestNotesParams = RequestNotesParams { max_count: 1000 };
 
+/// Where the events of the old generator stop and the ones of the new
+/// generator start on reload.
+#[derive(Clone, Copy, Debug)]
+pub enum ReloadMode {
+    /// At the next `Marker` event, or at the next bar if there is none.
+    Marker,
+    Immediate,
+    Beat,
+    /// At the start of the next group of this many bars, counted from the
+    /// first bar.
+    Bars(u64),
+}
+
+impl FromStr for ReloadMode {
+    type Err = anyhow::Error;
+
+    /// "marker", "now", "beat", "bar" or e.g. "4bars".
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "marker" => Ok(ReloadMode::Marker),
+            "now" => Ok(ReloadMode::Immediate),
+            "beat" => Ok(ReloadMode::Beat),
+            "bar" => Ok(ReloadMode::Bars(1)),
+            _ => match s.strip_suffix("bars").map(str::parse) {
+                Some(Ok(bars)) if bars > 0 => Ok(ReloadMode::Bars(bars)),
+                _ => bail!(
+                    "Unknown reload mode {:?}, expected marker, now, beat, bar or <n>bars",
+                    s
+                ),
+            },
+        }
+    }
+}
+
+// the buffered events, the position of the first one and the notes the
+// player was handed that are still on.
+#[derive(Default)]
+struct EventBuffer {
+    events: VecDeque<Event>,
+    front_position: Position,
+    sounding_notes: BTreeSet<NoteValue>,
+}
+
+fn track_note(notes: &mut BTreeSet<NoteValue>, event: &Event) {
+    match event {
+        Event::NoteOn(e) => {
+            notes.insert(e.note);
+        }
+        Event::NoteOff(e) => {
+            notes.remove(&e.note);
+        }
+        Event::AllNotesOff(_) => notes.clear(),
+        _ => (),
+    }
+}
+
+impl EventBuffer {
+    fn pop_front(&mut self) -> Option<Event> {
+        let event = self.events.pop_front()?;
+        self.front_position.advance(&event);
+        track_note(&mut self.sounding_notes, &event);
+        Some(event)
+    }
+
+    // where the generator continues.
+    fn end_position(&self) -> Position {
+        let mut position = self.front_position;
+        for event in self.events.iter() {
+            position.advance(event);
+        }
+        position
+    }
+
+    // drops the events the new generator replaces.
+    fn truncate_for_reload(&mut self, mode: ReloadMode) {
+        match mode {
+            ReloadMode::Marker => {
+                match self.events.iter().position(|e| matches!(e, Event::Marker)) {
+                    Some(i) => self.cut(i),
+                    None => {
+                        info!("No marker found, reloading at the next bar");
+                        self.truncate_at_grid(Some(1));
+                    }
+                }
+            }
+            ReloadMode::Immediate => self.cut(0),
+            ReloadMode::Beat => self.truncate_at_grid(None),
+            ReloadMode::Bars(bars) => self.truncate_at_grid(Some(bars)),
+        }
+    }
+
+    // drops the events from `index` on. Notes that are on at the cut, played
+    // already or started by the events kept, are stopped right there so they
+    // don't hang.
+    fn cut(&mut self, index: usize) {
+        self.events.truncate(index);
+
+        let mut on_at_cut = self.sounding_notes.clone();
+        for event in self.events.iter() {
+            track_note(&mut on_at_cut, event);
+        }
+
+        for note in on_at_cut {
+            self.events.push_back(Event::NoteOff(NoteOff { note }));
+        }
+    }
+
+    // cuts at the next beat or group of bars, shortening a wait that crosses
+    // it, or pads with a wait if the buffer ends before it.
+    fn truncate_at_grid(&mut self, bars: Option<u64>) {
+        let mut position = self.front_position;
+        let mut boundary = position.bars.next_boundary(position.tick, bars);
+        let mut cut = self.events.len();
+
+        for (i, event) in self.events.iter_mut().enumerate() {
+            if position.tick >= boundary {
+                cut = i;
+                break;
+            }
+
+            if let Event::Wait(wait) = event {
+                let remaining = boundary - position.tick;
+                if u64::from(wait.ticks) > remaining {
+                    wait.ticks = remaining as Ticks;
+                }
+            }
+
+            position.advance(event);
+
+            // the grid moves with the time signature.
+            if let Event::TimeSignature(_) = event {
+                boundary = position.bars.next_boundary(position.tick, bars);
+            }
+        }
+
+        self.cut(cut);
+
+        while position.tick < boundary {
+            let ticks = Ticks::try_from(boundary - position.tick).unwrap_or(Ticks::MAX);
+            let wait = Event::Wait(Wait { ticks });
+            position.advance(&wait);
+            self.events.push_back(wait);
+        }
+
+        debug!("Reloading at {}", position.bars.bar_beat_tick(boundary));
+    }
+}
+
 struct EventCoordinatorActor {
-    entrypoint: PathBuf,
+    config: EventGeneratorConfig,
+    reload_mode: ReloadMode,
     rx: Receiver<Msg>,
-    events: Arc<Mutex<VecDeque<Event>>>,
+    events: Arc<Mutex<EventBuffer>>,
     ega: Option<EventGeneratorActorHandle>,
     ega_join_handles: Vec<JoinHandle<()>>,
 }
@@ -29,16 +179,16 @@ struct EventCoordinatorActor {
 #[derive(Debug)]
 pub enum Msg {
     LoadMoreEvents { params: RequestNotesParams },
-    ReloadFromNextMarker,
+    Reload,
     Exit,
 }
 
 impl EventCoordinatorActor {
-    pub fn new(entrypoint: PathBuf, rx: Receiver<Msg>) -> Self {
-        let events = Arc::new(Mutex::new(VecDeque::<Event>::new()));
+    pub fn new(config: EventGeneratorConfig, reload_mode: ReloadMode, rx: Receiver<Msg>) -> Self {
+        let events = Arc::new(Mutex::new(EventBuffer::default()));
         let mut ega_join_handles = vec![];
 
-        let ega = match Self::initialize_ega(&entrypoint) {
+        let ega = match Self::initialize_ega(&config) {
             Ok((ega, ega_jh)) => {
                 ega_join_handles.push(ega_jh);
                 Some(ega)
@@ -53,7 +203,8 @@ impl EventCoordinatorActor {
             rx,
             events,
             ega,
-            entrypoint,
+            config,
+            reload_mode,
             ega_join_handles,
         };
 
@@ -71,8 +222,8 @@ impl EventCoordinatorActor {
                     self = self.load_more_events(params);
                 }
 
-                Ok(Msg::ReloadFromNextMarker) => {
-                    self = self.reload_from_next_marker();
+                Ok(Msg::Reload) => {
+                    self = self.reload();
                 }
 
                 Ok(Msg::Exit) => break,
@@ -86,10 +237,9 @@ impl EventCoordinatorActor {
 
         // send exit signal to current event generator, otherwise we won't ever
         // be able to join it
-        match self.ega {
-            Some(ega) => ega.exit().unwrap(),
-            None => (),
-        };
+        if let Some(ega) = self.ega {
+            ega.exit().unwrap();
+        }
 
         for jh in self.ega_join_handles {
             jh.join().unwrap();
@@ -98,8 +248,8 @@ impl EventCoordinatorActor {
         Ok(())
     }
 
-    fn reload_from_next_marker(mut self) -> Self {
-        let new_ega = match Self::initialize_ega(&self.entrypoint) {
+    fn reload(mut self) -> Self {
+        let new_ega = match Self::initialize_ega(&self.config) {
             Ok((ega, ega_jh)) => {
                 self.ega_join_handles.push(ega_jh);
                 ega
@@ -110,28 +260,17 @@ impl EventCoordinatorActor {
             }
         };
 
-        {
-            let mut evs = self.events.lock().unwrap();
-            let marker_index = evs.iter().position(|e| matches!(e, Event::Marker));
-
-            match marker_index {
-                Some(i) => evs.truncate(i),
-
-                // if there is no marker, we should queue events until we do.
-                None => {
-                    warn!("No marker found, clearing all events");
-                    evs.clear()
-                }
-            }
-        }
+        self.events
+            .lock()
+            .unwrap()
+            .truncate_for_reload(self.reload_mode);
 
         {
             let old_ega = self.ega;
             self.ega = Some(new_ega);
 
-            match old_ega {
-                Some(ega) => ega.exit().log_err(),
-                None => (),
+            if let Some(ega) = old_ega {
+                ega.exit().log_err();
             }
 
             // TODO: old_ega is probably going to get
@@ -146,12 +285,14 @@ impl EventCoordinatorActor {
     fn load_more_events(self, params: RequestNotesParams) -> Self {
         match &self.ega {
             Some(ega) => {
-                match ega.get_events(params) {
+                let position = self.events.lock().unwrap().end_position();
+
+                match ega.get_events(params, position) {
                     Ok(res) => {
                         let mut events = self.events.lock().unwrap();
 
                         for event in res.events {
-                            events.push_back(event);
+                            events.events.push_back(event);
                         }
                     }
 
@@ -170,10 +311,10 @@ impl EventCoordinatorActor {
     }
 
     fn initialize_ega(
-        entrypoint: &Path,
+        config: &EventGeneratorConfig,
     ) -> anyhow::Result<(EventGeneratorActorHandle, JoinHandle<()>)> {
         let (initialized_tx, initialized_rx) = bounded(0);
-        let (ega, ega_jh) = new_event_generator_actor(entrypoint, initialized_tx);
+        let (ega, ega_jh) = new_event_generator_actor(config.clone(), initialized_tx);
 
         match initialized_rx.recv() {
             Ok(Ok(())) => Ok((ega, ega_jh)),
@@ -184,10 +325,11 @@ impl EventCoordinatorActor {
 }
 
 pub fn new_event_coordinator(
-    entrypoint: &Path,
+    config: EventGeneratorConfig,
+    reload_mode: ReloadMode,
 ) -> (EventCoordinatorActorHandle, JoinHandle<anyhow::Result<()>>) {
     let (tx, rx) = unbounded();
-    let ega = EventCoordinatorActor::new(entrypoint.to_path_buf(), rx);
+    let ega = EventCoordinatorActor::new(config, reload_mode, rx);
     let events = ega.events.clone();
 
     let jh = spawn(move || -> Result<(), anyhow::Error> {
@@ -205,7 +347,7 @@ pub fn new_event_coordinator(
 #[derive(Clone)]
 pub struct EventCoordinatorActorHandle {
     tx: Sender<Msg>,
-    events: Arc<Mutex<VecDeque<Event>>>,
+    events: Arc<Mutex<EventBuffer>>,
 }
 
 impl EventCoordinatorActorHandle {
@@ -214,8 +356,10 @@ impl EventCoordinatorActorHandle {
         Ok(())
     }
 
-    pub fn reload_from_next_marker(&self) -> anyhow::Result<()> {
-        self.tx.send(Msg::ReloadFromNextMarker)?;
+    /// Replaces the event generator, switching over as set by the
+    /// `ReloadMode`.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        self.tx.send(Msg::Reload)?;
         Ok(())
     }
 
@@ -234,16 +378,139 @@ impl PlayerEventSource for EventCoordinatorActorHandle {
         {
             let mut events = self.events.lock().unwrap();
             event = events.pop_front();
-            need_mode = events.len() < REQUEST_MORE_WHEN_COUNT_UNDER
+            need_mode = events.events.len() < REQUEST_MORE_WHEN_COUNT_UNDER
         }
 
         if need_mode {
-            match self.load_more_events(REQUEST_PARAMS) {
-                Err(e) => warn!("Could not request for more events: {:?}", e),
-                _ => (),
+            if let Err(e) = self.load_more_events(REQUEST_PARAMS) {
+                warn!("Could not request for more events: {:?}", e);
             }
         }
 
         event
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{NoteOn, TICKS_PER_BEAT};
+
+    fn wait(beats: f64) -> Event {
+        Event::Wait(Wait {
+            ticks: (f64::from(TICKS_PER_BEAT) * beats) as Ticks,
+        })
+    }
+
+    fn note_on() -> Event {
+        Event::NoteOn(NoteOn { note: 60 })
+    }
+
+    fn ticks(events: &EventBuffer) -> Vec<Option<Ticks>> {
+        events
+            .events
+            .iter()
+            .map(|event| match event {
+                Event::Wait(wait) => Some(wait.ticks),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reload_at_bar_shortens_the_wait_crossing_it() {
+        let mut events = EventBuffer {
+            events: [note_on(), wait(3.0), note_on(), wait(3.0), note_on()].into(),
+            ..Default::default()
+        };
+        events.pop_front();
+        events.pop_front();
+
+        // at beat 3 of bar 0, so the cut is one beat later.
+        events.truncate_for_reload(ReloadMode::Bars(1));
+
+        // the note started before the cut is stopped at it.
+        assert_eq!(ticks(&events), vec![None, Some(TICKS_PER_BEAT), None]);
+        assert_eq!(events.end_position().tick, u64::from(TICKS_PER_BEAT) * 4);
+    }
+
+    #[test]
+    fn reload_pads_to_the_grid_when_the_buffer_is_short() {
+        let mut events = EventBuffer {
+            events: [wait(0.5), note_on()].into(),
+            ..Default::default()
+        };
+        events.pop_front();
+
+        events.truncate_for_reload(ReloadMode::Beat);
+
+        assert_eq!(ticks(&events), vec![None, None, Some(TICKS_PER_BEAT / 2)]);
+    }
+
+    #[test]
+    fn reload_stops_notes_whose_note_off_was_dropped() {
+        let mut events = EventBuffer {
+            events: [
+                Event::NoteOn(NoteOn { note: 60 }),
+                wait(1.0),
+                Event::Marker,
+                wait(1.0),
+                Event::NoteOff(NoteOff { note: 60 }),
+                note_on(),
+                Event::NoteOff(NoteOff { note: 60 }),
+            ]
+            .into(),
+            ..Default::default()
+        };
+
+        events.truncate_for_reload(ReloadMode::Marker);
+
+        assert!(matches!(
+            events.events.back(),
+            Some(Event::NoteOff(NoteOff { note: 60 }))
+        ));
+        assert_eq!(events.events.len(), 3);
+    }
+
+    #[test]
+    fn reload_stops_notes_without_a_note_off_in_the_buffer() {
+        let mut events = EventBuffer {
+            events: [
+                Event::NoteOn(NoteOn { note: 48 }),
+                wait(1.0),
+                Event::NoteOn(NoteOn { note: 60 }),
+                Event::NoteOn(NoteOn { note: 64 }),
+                Event::NoteOff(NoteOff { note: 64 }),
+                wait(1.0),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        // the player already played the first note.
+        events.pop_front();
+
+        events.truncate_for_reload(ReloadMode::Immediate);
+
+        let note_offs: Vec<NoteValue> = events
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::NoteOff(e) => Some(e.note),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(note_offs, vec![48]);
+
+        let mut events = EventBuffer {
+            events: [note_on(), wait(1.0), Event::Marker, wait(1.0)].into(),
+            ..Default::default()
+        };
+
+        events.truncate_for_reload(ReloadMode::Marker);
+
+        assert!(matches!(
+            events.events.back(),
+            Some(Event::NoteOff(NoteOff { note: 60 }))
+        ));
+    }
+}
@@ -1,23 +1,44 @@
-use crate::{event::Event, ts_module_loader::TypescriptModuleLoader};
+use crate::{
+    event::Event,
+    event_schema::validate_event,
+    import_map::ImportMap,
+    js_error::render_js_error,
+    ops::{murmel_extension, OpsContext},
+    position::Position,
+    snapshot::RuntimeSnapshot,
+    transpile_cache::TranspileCache,
+    ts_module_loader::{SourceMapStore, TypescriptModuleLoader},
+};
 
-use std::{path::Path, rc::Rc};
+use std::{path::PathBuf, rc::Rc, time::Duration};
 
 use anyhow::anyhow;
 use deno_core::{
-    serde_v8,
+    error::JsError,
+    serde_json, serde_v8,
     url::Url,
     v8::{self, HandleScope},
-    JsRuntime, ModuleId, RuntimeOptions,
+    JsRuntime, ModuleId, RuntimeOptions, Snapshot,
 };
 use log::{debug, info};
 use serde::Deserialize;
 use tokio::runtime::Runtime;
 
+// how long the promise returned by an async generator's next() may take to
+// settle before the events so far are returned, so a slow promise does not
+// stall playback.
+const ASYNC_NEXT_TIMEOUT: Duration = Duration::from_millis(500);
+
 pub struct EventGenerator {
-    #[allow(dead_code)]
     async_runtime: Runtime,
     js_runtime: JsRuntime,
     module_id: ModuleId,
+    main_module_url: Url,
+    source_maps: SourceMapStore,
+    strict: bool,
+    // a promise returned by next() that did not settle in time, awaited
+    // again on the next request instead of calling next() again.
+    pending_next: Option<v8::Global<v8::Value>>,
 }
 
 pub struct RequestNotesResult {
@@ -25,28 +46,71 @@ pub struct RequestNotesResult {
     pub has_more: bool,
 }
 
+/// Everything needed to create an event generator. The coordinator keeps it
+/// to create a new generator on every reload.
+#[derive(Clone)]
+pub struct EventGeneratorConfig {
+    pub entrypoint: PathBuf,
+    pub ops_context: OpsContext,
+    /// Validate every yielded event against the event schema, instead of
+    /// ignoring unknown fields.
+    pub strict: bool,
+    pub transpile_cache: TranspileCache,
+    /// Runtime snapshot the generator is created from, see `snapshot`.
+    pub snapshot: Option<RuntimeSnapshot>,
+}
+
 #[derive(Debug)]
 pub struct RequestNotesParams {
     pub max_count: u32,
 }
 
 impl EventGenerator {
-    pub fn create(entrypoint: &Path) -> anyhow::Result<EventGenerator> {
+    pub fn create(config: EventGeneratorConfig) -> anyhow::Result<EventGenerator> {
+        let entrypoint = &config.entrypoint;
         let async_runtime = Runtime::new().unwrap();
 
         info!("Initializing JS runtime");
 
+        let source_maps = SourceMapStore::default();
+        // read on every reload, so changes to it apply without a restart.
+        let import_map = ImportMap::for_entrypoint(entrypoint)?;
+
+        let module_loader = Rc::new(TypescriptModuleLoader {
+            source_maps: source_maps.clone(),
+            transpile_cache: config.transpile_cache,
+            import_map,
+            snapshot_modules: config.snapshot.as_ref().map(|s| s.modules.clone()),
+        });
+
         let mut js_runtime = JsRuntime::new(RuntimeOptions {
-            module_loader: Some(Rc::new(TypescriptModuleLoader)),
+            module_loader: Some(module_loader.clone()),
+            source_map_getter: Some(Box::new(source_maps.clone())),
+            extensions: vec![murmel_extension(config.ops_context)],
+            startup_snapshot: config.snapshot.map(|s| Snapshot::Static(s.data)),
             ..Default::default()
         });
+        // for console to log positions in the source.
+        js_runtime.op_state().borrow_mut().put(source_maps.clone());
 
         info!(
             "Loading main module from path {}",
             entrypoint.to_string_lossy()
         );
 
-        let module_id = load_main_module(async_runtime.handle(), &mut js_runtime, entrypoint)?;
+        let main_module_url = Url::from_file_path(entrypoint).map_err(|()| {
+            anyhow!(
+                "Could not get URL from entrypoint {}",
+                entrypoint.as_os_str().to_string_lossy()
+            )
+        })?;
+
+        module_loader.check_default_export(entrypoint)?;
+
+        let module_id = load_main_module(async_runtime.handle(), &mut js_runtime, &main_module_url)
+            .map_err(|e| render_js_error(e, &source_maps))?;
+
+        check_default_export_is_iterator(&mut js_runtime, module_id)?;
 
         info!("Main module loaded");
 
@@ -54,28 +118,52 @@ impl EventGenerator {
             js_runtime,
             async_runtime,
             module_id,
+            main_module_url,
+            source_maps,
+            strict: config.strict,
+            pending_next: None,
         })
     }
 
     pub fn request_notes(
         &mut self,
         params: RequestNotesParams,
+        mut position: Position,
     ) -> Result<RequestNotesResult, anyhow::Error> {
-        let module = self.js_runtime.get_module_namespace(self.module_id)?;
-        let isolate = self.js_runtime.v8_isolate();
-        let val = module.open(isolate);
-
-        let scope = &mut self.js_runtime.handle_scope();
-        let default_str = v8::String::new(scope, "default").unwrap();
-        let default_export = val.get(scope, default_str.into()).unwrap();
+        let default_export = {
+            let module = self.js_runtime.get_module_namespace(self.module_id)?;
+            let scope = &mut self.js_runtime.handle_scope();
+            let module = v8::Local::new(scope, module);
+            let default_str = v8::String::new(scope, "default").unwrap();
+            let default_export = module.get(scope, default_str.into()).unwrap();
+            v8::Global::new(scope, default_export)
+        };
 
         let mut count = 0;
         let mut events = vec![];
         let mut has_more = true;
 
         while count < params.max_count {
-            let EventGeneratorResult { done, value } =
-                call_generator_function(scope, default_export)?;
+            // what the script sees as `murmel.transport`.
+            self.js_runtime.op_state().borrow_mut().put(position);
+
+            let next_event = self.next_event(&default_export).map_err(|e| {
+                render_js_error(e, &self.source_maps).context(format!(
+                    "Calling next() on the default export of {} failed after {} events",
+                    self.main_module_url, count
+                ))
+            })?;
+
+            let EventGeneratorResult { done, value } = match next_event {
+                Some(result) => result,
+                None => {
+                    debug!(
+                        "The promise returned by next() did not settle within {:?}, returning {} events",
+                        ASYNC_NEXT_TIMEOUT, count
+                    );
+                    break;
+                }
+            };
 
             if done {
                 has_more = false;
@@ -85,6 +173,7 @@ impl EventGenerator {
             match value {
                 Some(event) => {
                     count += 1;
+                    position.advance(&event);
                     events.push(event)
                 }
                 None => {
@@ -95,6 +184,46 @@ impl EventGenerator {
 
         Ok(RequestNotesResult { events, has_more })
     }
+
+    // `None` if next() returned a promise that is still pending.
+    fn next_event(
+        &mut self,
+        iterable: &v8::Global<v8::Value>,
+    ) -> Result<Option<EventGeneratorResult>, anyhow::Error> {
+        let (result, is_promise) = match self.pending_next.take() {
+            Some(promise) => (promise, true),
+            None => {
+                let scope = &mut self.js_runtime.handle_scope();
+                let iterable = v8::Local::new(scope, iterable);
+                let result = call_generator_function(scope, iterable)?;
+                (v8::Global::new(scope, result), result.is_promise())
+            }
+        };
+
+        // async generators return a promise of the result, which is settled
+        // by running the event loop.
+        let result = if is_promise {
+            let js_runtime = &mut self.js_runtime;
+            let settled = self.async_runtime.block_on(async {
+                tokio::time::timeout(ASYNC_NEXT_TIMEOUT, js_runtime.resolve_value(result.clone()))
+                    .await
+            });
+
+            match settled {
+                Ok(value) => value?,
+                Err(_) => {
+                    self.pending_next = Some(result);
+                    return Ok(None);
+                }
+            }
+        } else {
+            result
+        };
+
+        let scope = &mut self.js_runtime.handle_scope();
+        let result = v8::Local::new(scope, result);
+        deserialize_result(scope, result, self.strict).map(Some)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -103,20 +232,20 @@ struct EventGeneratorResult {
     pub done: bool,
 }
 
+// used in strict mode, so the event can be validated before deserializing.
+#[derive(Deserialize, Debug)]
+struct UncheckedEventGeneratorResult {
+    pub value: Option<serde_json::Value>,
+    pub done: bool,
+}
+
 fn load_main_module(
     executor: &tokio::runtime::Handle,
     js_runtime: &mut JsRuntime,
-    entrypoint: &Path,
+    url: &Url,
 ) -> Result<ModuleId, anyhow::Error> {
-    let url = Url::from_file_path(entrypoint).map_err(|()| {
-        anyhow!(
-            "Could not get URL from entrypoint {}",
-            entrypoint.as_os_str().to_string_lossy()
-        )
-    })?;
-
     let future = async {
-        let module_id = js_runtime.load_main_module(&url, None).await?;
+        let module_id = js_runtime.load_main_module(url, None).await?;
         let eval = js_runtime.mod_evaluate(module_id);
         js_runtime.run_event_loop(true).await?;
         eval.await??;
@@ -128,10 +257,35 @@ fn load_main_module(
     Ok(module_id)
 }
 
-fn call_generator_function(
-    scope: &mut HandleScope,
+fn check_default_export_is_iterator(
+    js_runtime: &mut JsRuntime,
+    module_id: ModuleId,
+) -> Result<(), anyhow::Error> {
+    let module = js_runtime.get_module_namespace(module_id)?;
+    let isolate = js_runtime.v8_isolate();
+    let val = module.open(isolate);
+
+    let scope = &mut js_runtime.handle_scope();
+    let default_str = v8::String::new(scope, "default").unwrap();
+    let next_str = v8::String::new(scope, "next").unwrap();
+
+    let next_fn = val
+        .get(scope, default_str.into())
+        .and_then(|default_export| default_export.to_object(scope))
+        .and_then(|default_export| default_export.get(scope, next_str.into()));
+
+    match next_fn {
+        Some(next_fn) if next_fn.is_function() => Ok(()),
+        _ => Err(anyhow!(
+            "Default export of the main module is not an iterator, it has no next() function"
+        )),
+    }
+}
+
+fn call_generator_function<'s>(
+    scope: &mut HandleScope<'s>,
     iterable: v8::Local<v8::Value>,
-) -> Result<EventGeneratorResult, anyhow::Error> {
+) -> Result<v8::Local<'s, v8::Value>, anyhow::Error> {
     if iterable.is_undefined() || iterable.is_null() {
         return Err(anyhow!("Iterable was undefined or null"));
     }
@@ -147,11 +301,37 @@ fn call_generator_function(
     let next_fn = v8::Local::<v8::Function>::try_from(next_fn_value)
         .map_err(|e| anyhow::Error::new(e).context("Excepted next() to be a function"))?;
 
-    let result_value = next_fn
-        .call(scope, iterable, &[])
-        .ok_or_else(|| anyhow!("Calling next() failed"))?;
+    let scope = &mut v8::TryCatch::new(scope);
 
-    let result = serde_v8::from_v8::<EventGeneratorResult>(scope, result_value)?;
+    match next_fn.call(scope, iterable, &[]) {
+        Some(result_value) => Ok(result_value),
+        None => match scope.exception() {
+            Some(exception) => Err(JsError::from_v8_exception(scope, exception).into()),
+            None => Err(anyhow!("Calling next() failed")),
+        },
+    }
+}
+
+fn deserialize_result(
+    scope: &mut HandleScope,
+    result_value: v8::Local<v8::Value>,
+    strict: bool,
+) -> Result<EventGeneratorResult, anyhow::Error> {
+    if !strict {
+        let result = serde_v8::from_v8::<EventGeneratorResult>(scope, result_value)?;
+        return Ok(result);
+    }
+
+    let UncheckedEventGeneratorResult { value, done } =
+        serde_v8::from_v8::<UncheckedEventGeneratorResult>(scope, result_value)?;
+
+    let value = match value {
+        Some(value) if !done => {
+            validate_event(&value).map_err(|e| anyhow!("Invalid event {}:\n{}", value, e))?;
+            Some(serde_json::from_value(value)?)
+        }
+        _ => None,
+    };
 
-    Ok(result)
+    Ok(EventGeneratorResult { value, done })
 }
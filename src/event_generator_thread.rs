@@ -1,14 +1,15 @@
-use crate::event_generator::{EventGenerator, RequestNotesParams, RequestNotesResult};
+use crate::event_generator::{
+    EventGenerator, EventGeneratorConfig, RequestNotesParams, RequestNotesResult,
+};
+use crate::position::Position;
 use crossbeam::channel::{bounded, unbounded, Sender};
 use log::debug;
-use std::{
-    path::Path,
-    thread::{spawn, JoinHandle},
-};
+use std::thread::{spawn, JoinHandle};
 
 enum Msg {
     GetEvents {
         params: RequestNotesParams,
+        position: Position,
         sndr: Sender<Result<RequestNotesResult, anyhow::Error>>,
     },
 
@@ -16,17 +17,16 @@ enum Msg {
 }
 
 pub fn new_event_generator_actor(
-    entrypoint: &Path,
+    config: EventGeneratorConfig,
     initialized: Sender<anyhow::Result<()>>,
 ) -> (EventGeneratorActorHandle, JoinHandle<()>) {
-    let entrypoint = entrypoint.to_path_buf();
     let (tx, rx) = unbounded();
 
     let thread = spawn(move || {
         debug!("Event generator thread started, creating event generator");
         let mut event_generator;
 
-        match EventGenerator::create(&entrypoint) {
+        match EventGenerator::create(config) {
             Ok(eg) => {
                 event_generator = eg;
                 let _ = initialized.send(Ok(()));
@@ -41,8 +41,12 @@ pub fn new_event_generator_actor(
         debug!("Event generator created");
         for e in rx.iter() {
             match e {
-                Msg::GetEvents { params, sndr } => {
-                    let _ = sndr.send(event_generator.request_notes(params));
+                Msg::GetEvents {
+                    params,
+                    position,
+                    sndr,
+                } => {
+                    let _ = sndr.send(event_generator.request_notes(params, position));
                 }
 
                 Msg::Exit => break,
@@ -63,9 +67,19 @@ pub struct EventGeneratorActorHandle {
 }
 
 impl EventGeneratorActorHandle {
-    pub fn get_events(&self, params: RequestNotesParams) -> anyhow::Result<RequestNotesResult> {
+    /// `position` is where the generated events will start, see
+    /// `murmel.transport`.
+    pub fn get_events(
+        &self,
+        params: RequestNotesParams,
+        position: Position,
+    ) -> anyhow::Result<RequestNotesResult> {
         let (tx, rx) = bounded(0);
-        self.tx.send(Msg::GetEvents { params, sndr: tx })?;
+        self.tx.send(Msg::GetEvents {
+            params,
+            position,
+            sndr: tx,
+        })?;
         rx.recv()?
     }
 
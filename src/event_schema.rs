@@ -0,0 +1,293 @@
+use crate::event::TimeSignature;
+use deno_core::serde_json::{Map, Value};
+
+// Keep in sync with `event::Event`. Also the source of the `Event` type
+// emitted by `murmel types`.
+pub const EVENT_SCHEMA: &[(&str, &[(&str, FieldKind)])] = &[
+    ("NoteOn", &[("note", FieldKind::MidiValue)]),
+    ("NoteOff", &[("note", FieldKind::MidiValue)]),
+    ("Wait", &[("ticks", FieldKind::Ticks)]),
+    ("AllNotesOff", &[]),
+    ("ChangeBpm", &[("bpm", FieldKind::Bpm)]),
+    (
+        "TempoRamp",
+        &[
+            ("toBpm", FieldKind::Bpm),
+            ("overTicks", FieldKind::Ticks),
+            ("curve", FieldKind::RampCurve),
+        ],
+    ),
+    (
+        "TimeSignature",
+        &[
+            ("numerator", FieldKind::Numerator),
+            ("denominator", FieldKind::Denominator),
+        ],
+    ),
+    ("Print", &[("value", FieldKind::String)]),
+    ("Marker", &[]),
+];
+
+pub enum FieldKind {
+    MidiValue,
+    Ticks,
+    /// May be fractional.
+    Bpm,
+    Numerator,
+    /// A power of two.
+    Denominator,
+    /// One of `RAMP_CURVES`.
+    RampCurve,
+    String,
+}
+
+// the serialized `event::RampCurve` variants.
+const RAMP_CURVES: &[&str] = &["linear", "exponential"];
+
+impl FieldKind {
+    // inclusive, `None` for strings.
+    fn range(&self) -> Option<(u64, u64)> {
+        match self {
+            FieldKind::String | FieldKind::RampCurve => None,
+            FieldKind::MidiValue => Some((0, 127)),
+            FieldKind::Ticks => Some((0, u64::from(u32::MAX))),
+            FieldKind::Bpm => Some((1, u64::from(u16::MAX))),
+            FieldKind::Numerator => Some((1, u64::from(u8::MAX))),
+            FieldKind::Denominator => Some((1, u64::from(TimeSignature::MAX_DENOMINATOR))),
+        }
+    }
+
+    pub fn typescript_type(&self) -> String {
+        match self {
+            FieldKind::RampCurve => RAMP_CURVES
+                .iter()
+                .map(|curve| format!("'{}'", curve))
+                .collect::<Vec<_>>()
+                .join(" | "),
+            _ if self.range().is_some() => "number".to_string(),
+            _ => "string".to_string(),
+        }
+    }
+
+    /// What `check` accepts, e.g. "an integer between 0 and 127".
+    pub fn description(&self) -> String {
+        match self {
+            FieldKind::Denominator => {
+                return format!(
+                    "a power of two between 1 and {}",
+                    TimeSignature::MAX_DENOMINATOR
+                )
+            }
+            FieldKind::RampCurve => return format!("one of {}", self.typescript_type()),
+            _ => (),
+        }
+
+        match self.range() {
+            Some((min, max)) if matches!(self, FieldKind::Bpm) => {
+                format!("a number between {} and {}", min, max)
+            }
+            Some((min, max)) => format!("an integer between {} and {}", min, max),
+            None => "a string".to_string(),
+        }
+    }
+
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let (min, max) = match self.range() {
+            Some(range) => range,
+            None => {
+                let valid = match self {
+                    FieldKind::RampCurve => value
+                        .as_str()
+                        .map_or(false, |curve| RAMP_CURVES.contains(&curve)),
+                    _ => value.is_string(),
+                };
+
+                return if valid {
+                    Ok(())
+                } else {
+                    Err(format!("expected {}, got {}", self.description(), value))
+                };
+            }
+        };
+
+        if let FieldKind::Bpm = self {
+            return match value.as_f64() {
+                Some(n) if n >= min as f64 && n <= max as f64 => Ok(()),
+                _ => Err(format!("expected {}, got {}", self.description(), value)),
+            };
+        }
+
+        let needs_power_of_two = matches!(self, FieldKind::Denominator);
+
+        match value.as_u64() {
+            Some(n) if (min..=max).contains(&n) && (!needs_power_of_two || n.is_power_of_two()) => {
+                Ok(())
+            }
+            _ => Err(format!("expected {}, got {}", self.description(), value)),
+        }
+    }
+}
+
+/// Checks a yielded event against the event schema, reporting every problem
+/// with the field it concerns, e.g. `NoteOn.nte: unknown field`.
+pub fn validate_event(value: &Value) -> Result<(), String> {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return Err(format!("expected an event object, got {}", value)),
+    };
+
+    let event_type = match object.get("type") {
+        Some(Value::String(event_type)) => event_type,
+        Some(other) => return Err(format!("type: expected a string, got {}", other)),
+        None => return Err("type: missing".to_string()),
+    };
+
+    let fields = EVENT_SCHEMA
+        .iter()
+        .find(|(name, _)| name == event_type)
+        .map(|(_, fields)| *fields)
+        .ok_or_else(|| {
+            let known: Vec<&str> = EVENT_SCHEMA.iter().map(|(name, _)| *name).collect();
+            format!(
+                "type: unknown event type {:?}, expected one of {}",
+                event_type,
+                known.join(", ")
+            )
+        })?;
+
+    let errors = field_errors(event_type, fields, object);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn field_errors(
+    event_type: &str,
+    fields: &[(&str, FieldKind)],
+    object: &Map<String, Value>,
+) -> Vec<String> {
+    let mut errors = vec![];
+
+    for (field, kind) in fields {
+        match object.get(*field) {
+            Some(value) => {
+                if let Err(e) = kind.check(value) {
+                    errors.push(format!("{}.{}: {}", event_type, field, e));
+                }
+            }
+            None => errors.push(format!("{}.{}: missing", event_type, field)),
+        }
+    }
+
+    for key in object.keys() {
+        if key != "type" && !fields.iter().any(|(field, _)| field == key) {
+            errors.push(format!("{}.{}: unknown field", event_type, key));
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deno_core::serde_json::json;
+
+    fn error(event: Value) -> String {
+        validate_event(&event).unwrap_err()
+    }
+
+    #[test]
+    fn accepts_valid_events() {
+        for event in [
+            json!({"type": "NoteOn", "note": 60}),
+            json!({"type": "ChangeBpm", "bpm": 92.5}),
+            json!({"type": "TempoRamp", "toBpm": 180, "overTicks": 96, "curve": "linear"}),
+            json!({"type": "TimeSignature", "numerator": 7, "denominator": 8}),
+            json!({"type": "Print", "value": "hi"}),
+            json!({"type": "Marker"}),
+        ] {
+            assert_eq!(validate_event(&event), Ok(()), "{}", event);
+        }
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert_eq!(
+            error(json!({"type": "NoteOn", "note": 128})),
+            "NoteOn.note: expected an integer between 0 and 127, got 128"
+        );
+        assert_eq!(
+            error(json!({"type": "Wait", "ticks": -1})),
+            "Wait.ticks: expected an integer between 0 and 4294967295, got -1"
+        );
+        assert_eq!(
+            error(json!({"type": "ChangeBpm", "bpm": 0.5})),
+            "ChangeBpm.bpm: expected a number between 1 and 65535, got 0.5"
+        );
+        assert_eq!(
+            error(json!({"type": "TimeSignature", "numerator": 0, "denominator": 4})),
+            "TimeSignature.numerator: expected an integer between 1 and 255, got 0"
+        );
+    }
+
+    #[test]
+    fn rejects_fractions_where_integers_are_expected() {
+        assert_eq!(
+            error(json!({"type": "NoteOn", "note": 60.5})),
+            "NoteOn.note: expected an integer between 0 and 127, got 60.5"
+        );
+        assert_eq!(
+            error(json!({"type": "Wait", "ticks": "12"})),
+            "Wait.ticks: expected an integer between 0 and 4294967295, got \"12\""
+        );
+    }
+
+    #[test]
+    fn rejects_denominators_that_are_not_a_power_of_two() {
+        assert_eq!(
+            error(json!({"type": "TimeSignature", "numerator": 4, "denominator": 6})),
+            "TimeSignature.denominator: expected a power of two between 1 and 64, got 6"
+        );
+        assert!(validate_event(
+            &json!({"type": "TimeSignature", "numerator": 4, "denominator": 128})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_ramp_curves_and_non_strings() {
+        assert_eq!(
+            error(json!({"type": "TempoRamp", "toBpm": 90, "overTicks": 96, "curve": "cubic"})),
+            "TempoRamp.curve: expected one of 'linear' | 'exponential', got \"cubic\""
+        );
+        assert_eq!(
+            error(json!({"type": "Print", "value": 1})),
+            "Print.value: expected a string, got 1"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_missing_types() {
+        assert!(error(json!({"type": "NoteOnn", "note": 60}))
+            .starts_with("type: unknown event type \"NoteOnn\", expected one of NoteOn, NoteOff"));
+        assert_eq!(error(json!({"note": 60})), "type: missing");
+        assert_eq!(error(json!({"type": 1})), "type: expected a string, got 1");
+        assert_eq!(error(json!([60])), "expected an event object, got [60]");
+    }
+
+    #[test]
+    fn reports_every_missing_and_unknown_field() {
+        assert_eq!(
+            error(json!({"type": "NoteOn", "nte": 60})),
+            "NoteOn.note: missing\nNoteOn.nte: unknown field"
+        );
+        assert_eq!(
+            error(json!({"type": "TimeSignature", "numerator": 4})),
+            "TimeSignature.denominator: missing"
+        );
+    }
+}
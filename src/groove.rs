@@ -0,0 +1,214 @@
+use crate::event::TICKS_PER_BEAT;
+use anyhow::{bail, Context};
+use deno_core::serde_json;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+// groove templates have a step per sixteenth note.
+const TEMPLATE_SUBDIVISION: u64 = 16;
+
+/// Timing offsets and velocity scales for the steps of a grid, repeating
+/// from the start of playback. Events between steps are shifted by an
+/// offset interpolated from the steps around them, so their order never
+/// changes.
+///
+/// There is only a global groove, no groove per track: events don't carry a
+/// track or channel, everything a script yields is one track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Groove {
+    step_ticks: u64,
+    steps: Vec<GrooveStep>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GrooveStep {
+    /// In steps, later when positive. Between -0.5 and 0.5.
+    #[serde(default)]
+    pub offset: f64,
+    /// Multiplies the velocity of notes on the step.
+    #[serde(default = "default_velocity")]
+    pub velocity: f64,
+}
+
+fn default_velocity() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct GrooveFile {
+    steps: Vec<GrooveStep>,
+}
+
+impl Groove {
+    /// Delays every second step of `subdivision`, e.g. 8 for eighth notes,
+    /// so the pair is split `percent` to `100 - percent`. 50 is straight,
+    /// about 67 a triplet shuffle.
+    pub fn swing(percent: f64, subdivision: u64) -> anyhow::Result<Groove> {
+        if percent.is_nan() || percent <= 0.0 || percent >= 100.0 {
+            bail!("Swing {}% out of range, expected 0 to 100", percent);
+        }
+
+        let on_beat = GrooveStep {
+            offset: 0.0,
+            velocity: 1.0,
+        };
+        let off_beat = GrooveStep {
+            offset: percent / 50.0 - 1.0,
+            velocity: 1.0,
+        };
+
+        // the swung step may move further than half a step.
+        Groove::new(subdivision, vec![on_beat, off_beat], 1.0)
+    }
+
+    /// Parses the `--swing` argument, `<percent>[@<subdivision>]`. Swings
+    /// sixteenth notes without a subdivision.
+    pub fn parse_swing(arg: &str) -> anyhow::Result<Groove> {
+        let (percent, subdivision) = match arg.split_once('@') {
+            Some((percent, subdivision)) => (percent, subdivision.parse()?),
+            None => (arg, TEMPLATE_SUBDIVISION),
+        };
+
+        Groove::swing(percent.trim_end_matches('%').parse()?, subdivision)
+    }
+
+    /// Loads a groove template, a JSON file with a step per sixteenth note,
+    /// e.g. `{"steps": [{"offset": 0}, {"offset": 0.2, "velocity": 0.8}]}`.
+    pub fn load(path: &Path) -> anyhow::Result<Groove> {
+        let load = || -> anyhow::Result<Groove> {
+            let file: GrooveFile = serde_json::from_slice(&fs::read(path)?)?;
+            Groove::new(TEMPLATE_SUBDIVISION, file.steps, 0.5)
+        };
+
+        load().with_context(|| format!("Could not load groove {}", path.display()))
+    }
+
+    // offsets are limited so events can't swap places.
+    fn new(subdivision: u64, steps: Vec<GrooveStep>, max_offset: f64) -> anyhow::Result<Groove> {
+        let whole_note = u64::from(TICKS_PER_BEAT) * 4;
+        if subdivision == 0 || whole_note % subdivision != 0 {
+            bail!("Can't divide a whole note into {} steps", subdivision);
+        }
+        if steps.is_empty() {
+            bail!("A groove needs at least one step");
+        }
+
+        for (index, step) in steps.iter().enumerate() {
+            if step.offset.is_nan() || step.offset.abs() >= max_offset {
+                bail!(
+                    "Step {} offset {} out of range, expected -{} to {}",
+                    index + 1,
+                    step.offset,
+                    max_offset,
+                    max_offset
+                );
+            }
+            if step.velocity.is_nan() || step.velocity < 0.0 {
+                bail!(
+                    "Step {} velocity {} must not be negative",
+                    index + 1,
+                    step.velocity
+                );
+            }
+        }
+
+        Ok(Groove {
+            step_ticks: whole_note / subdivision,
+            steps,
+        })
+    }
+
+    fn step(&self, index: u64) -> GrooveStep {
+        self.steps[(index % self.steps.len() as u64) as usize]
+    }
+
+    /// How far events at `tick` are moved, in ticks.
+    pub fn offset_ticks(&self, tick: u64) -> i64 {
+        let index = tick / self.step_ticks;
+        let progress = (tick % self.step_ticks) as f64 / self.step_ticks as f64;
+        let (from, to) = (self.step(index).offset, self.step(index + 1).offset);
+
+        ((from + (to - from) * progress) * self.step_ticks as f64).round() as i64
+    }
+
+    /// Scales a note velocity by the step closest to `tick`.
+    pub fn velocity(&self, tick: u64, velocity: u8) -> u8 {
+        let index = (tick + self.step_ticks / 2) / self.step_ticks;
+        let scaled = (f64::from(velocity) * self.step(index).velocity).round();
+
+        scaled.clamp(1.0, 127.0) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEAT: u64 = TICKS_PER_BEAT as u64;
+
+    #[test]
+    fn swing_delays_every_second_step() {
+        let groove = Groove::parse_swing("75@8").unwrap();
+        let eighth = BEAT / 2;
+
+        assert_eq!(groove.offset_ticks(0), 0);
+        // 75% of a pair of eighths is an eighth and a half.
+        assert_eq!(groove.offset_ticks(eighth), (eighth / 2) as i64);
+        assert_eq!(groove.offset_ticks(BEAT), 0);
+        // halfway to the swung step, halfway to its offset.
+        assert_eq!(groove.offset_ticks(eighth / 2), (eighth / 4) as i64);
+    }
+
+    #[test]
+    fn straight_swing_changes_nothing() {
+        let groove = Groove::parse_swing("50%").unwrap();
+
+        for tick in [0, 1, BEAT / 4, BEAT / 3, BEAT * 7 + 5] {
+            assert_eq!(groove.offset_ticks(tick), 0);
+            assert_eq!(groove.velocity(tick, 100), 100);
+        }
+    }
+
+    #[test]
+    fn template_steps_repeat_and_scale_velocity() {
+        let sixteenth = BEAT / 4;
+        let groove = Groove::new(
+            TEMPLATE_SUBDIVISION,
+            vec![
+                GrooveStep {
+                    offset: 0.0,
+                    velocity: 1.0,
+                },
+                GrooveStep {
+                    offset: -0.25,
+                    velocity: 0.5,
+                },
+            ],
+            0.5,
+        )
+        .unwrap();
+
+        assert_eq!(groove.offset_ticks(sixteenth * 3), -(sixteenth as i64) / 4);
+        assert_eq!(groove.velocity(sixteenth * 3, 100), 50);
+        assert_eq!(groove.velocity(sixteenth * 4, 100), 100);
+    }
+
+    #[test]
+    fn loads_the_sample_template() {
+        let groove = Groove::load(Path::new("sample_scripts/groove.json")).unwrap();
+
+        assert_eq!(groove.steps.len(), 4);
+        assert_eq!(groove.velocity(BEAT / 4, 100), 70);
+    }
+
+    #[test]
+    fn template_offsets_are_limited() {
+        let step = GrooveStep {
+            offset: 0.6,
+            velocity: 1.0,
+        };
+
+        assert!(Groove::new(TEMPLATE_SUBDIVISION, vec![step], 0.5).is_err());
+        assert!(Groove::new(TEMPLATE_SUBDIVISION, vec![], 0.5).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    event_coordinator::EventCoordinatorActorHandle, params::ParamStore, player::PlayerActorHandle,
+};
+use crossbeam::channel::{unbounded, RecvTimeoutError, Sender};
+use log::{info, warn};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{io::stdin, thread::spawn, thread::JoinHandle, time::Duration};
+
+enum Command {
+    Play,
+    Stop,
+    Reload,
+    SetParam(String, f64),
+    Panic,
+    Exit,
+}
+
+/// Input loop for running without a terminal. Transport commands are read
+/// as lines from stdin ("play", "stop", "reload", "param <name> <value>",
+/// "panic", "quit"), SIGINT and SIGTERM exit and SIGHUP reloads.
+pub fn run_input_loop(
+    player: &PlayerActorHandle,
+    event_coordinator: &EventCoordinatorActorHandle,
+    params: &ParamStore,
+    player_jh: &JoinHandle<anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    let (tx, rx) = unbounded();
+
+    spawn_signal_listener(tx.clone())?;
+    spawn_stdin_listener(tx);
+
+    info!("Running headless, starting playback");
+    player.play()?;
+
+    while !player_jh.is_finished() {
+        let command = match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(command) => command,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        match command {
+            Command::Play => player.play()?,
+            Command::Stop => player.stop()?,
+            Command::Reload => event_coordinator.reload()?,
+            Command::SetParam(name, value) => params.set(&name, value),
+            Command::Panic => player.panic()?,
+            Command::Exit => {
+                player.exit()?;
+                event_coordinator.exit()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn spawn_signal_listener(tx: Sender<Command>) -> anyhow::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+
+    spawn(move || {
+        for signal in signals.forever() {
+            let command = match signal {
+                SIGHUP => Command::Reload,
+                _ => Command::Exit,
+            };
+
+            if tx.send(command).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn spawn_stdin_listener(tx: Sender<Command>) {
+    spawn(move || {
+        // stdin closing (e.g. /dev/null under systemd) is not a reason to
+        // stop playing, so just stop listening.
+        for line in stdin().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Could not read stdin: {:?}", e);
+                    break;
+                }
+            };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            let command = match words.as_slice() {
+                [] => continue,
+                ["play" | "p"] => Command::Play,
+                ["stop" | "s"] => Command::Stop,
+                ["reload" | "r"] => Command::Reload,
+                ["param", name, value] => match value.parse() {
+                    Ok(value) => Command::SetParam(name.to_string(), value),
+                    Err(e) => {
+                        warn!("Invalid parameter value {:?}: {:?}", value, e);
+                        continue;
+                    }
+                },
+                ["panic" | "x"] => Command::Panic,
+                ["quit" | "q" | "exit"] => Command::Exit,
+                _ => {
+                    warn!("Unknown command {:?}", line);
+                    continue;
+                }
+            };
+
+            if tx.send(command).is_err() {
+                break;
+            }
+        }
+    });
+}
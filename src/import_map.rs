@@ -0,0 +1,330 @@
+use anyhow::{anyhow, bail, Context};
+use deno_core::{serde_json, url::Url, ModuleSpecifier};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const PROJECT_FILE: &str = "murmel.json";
+const DEFAULT_IMPORT_MAP: &str = "import_map.json";
+
+// tried in order when a mapped path is not a file itself.
+const CANDIDATE_SUFFIXES: &[&str] = &[".ts", ".js", "/index.ts", "/index.js", "/mod.ts"];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProjectFile {
+    import_map: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ImportMapFile {
+    imports: HashMap<String, String>,
+    // not supported, but rejected rather than silently ignored.
+    scopes: Option<serde_json::Value>,
+}
+
+/// Maps bare specifiers like `tonal` or `murmel/std` to local files. Keys
+/// ending in `/` map every specifier starting with them, like in browser
+/// import maps.
+#[derive(Clone, Debug)]
+pub struct ImportMap {
+    path: PathBuf,
+    // longest key first, so the most specific mapping wins.
+    imports: Vec<(String, Url)>,
+}
+
+impl ImportMap {
+    /// Loads the import map of the project `entrypoint` is in: the one
+    /// `murmel.json` points at, or else `import_map.json`, both next to the
+    /// entrypoint. Returns `None` if there is neither.
+    pub fn for_entrypoint(entrypoint: &Path) -> anyhow::Result<Option<ImportMap>> {
+        let directory = entrypoint
+            .parent()
+            .ok_or_else(|| anyhow!("Entrypoint {} has no directory", entrypoint.display()))?;
+
+        let project_file_path = directory.join(PROJECT_FILE);
+        let import_map_path = if project_file_path.exists() {
+            let project_file: ProjectFile = serde_json::from_slice(&fs::read(&project_file_path)?)
+                .with_context(|| format!("Could not parse {}", project_file_path.display()))?;
+
+            match project_file.import_map {
+                Some(path) => directory.join(path),
+                None => return Ok(None),
+            }
+        } else {
+            let path = directory.join(DEFAULT_IMPORT_MAP);
+            if !path.exists() {
+                return Ok(None);
+            }
+            path
+        };
+
+        ImportMap::load(&import_map_path)
+            .with_context(|| format!("Could not load import map {}", import_map_path.display()))
+            .map(Some)
+    }
+
+    fn load(path: &Path) -> anyhow::Result<ImportMap> {
+        let path = fs::canonicalize(path)?;
+        let file: ImportMapFile = serde_json::from_slice(&fs::read(&path)?)?;
+        if file.scopes.is_some() {
+            bail!("Scopes are not supported, only imports");
+        }
+        let base = Url::from_file_path(&path)
+            .map_err(|()| anyhow!("Could not get URL from {}", path.display()))?;
+
+        let mut imports = file
+            .imports
+            .into_iter()
+            .map(|(key, target)| {
+                let url = base
+                    .join(&target)
+                    .with_context(|| format!("Invalid target {:?} for {:?}", target, key))?;
+
+                if key.ends_with('/') != url.as_str().ends_with('/') {
+                    bail!(
+                        "{:?} and its target {:?} must either both or neither end with /",
+                        key,
+                        target
+                    );
+                }
+
+                Ok((key, url))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        imports.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+
+        Ok(ImportMap { path, imports })
+    }
+
+    /// The modules mapped to a name, e.g. `tonal`, rather than a prefix.
+    /// Mappings that don't resolve are left out.
+    pub fn library_specifiers(&self) -> Vec<ModuleSpecifier> {
+        let referrer = self.path.to_string_lossy();
+
+        self.imports
+            .iter()
+            .filter(|(key, _)| !key.ends_with('/'))
+            .filter_map(|(key, _)| self.resolve(key, &referrer).ok())
+            .collect()
+    }
+
+    /// Resolves a bare specifier, or fails listing the paths that were tried.
+    pub fn resolve(&self, specifier: &str, referrer: &str) -> anyhow::Result<ModuleSpecifier> {
+        let url = self
+            .imports
+            .iter()
+            .find_map(|(key, target)| {
+                if specifier == key {
+                    Some(Ok(target.clone()))
+                } else if key.ends_with('/') {
+                    specifier
+                        .strip_prefix(key.as_str())
+                        .map(|rest| target.join(rest))
+                } else {
+                    None
+                }
+            })
+            .transpose()?
+            .ok_or_else(|| {
+                anyhow!(
+                    "Could not resolve {:?} imported from {}, it is not in the import map {}",
+                    specifier,
+                    referrer,
+                    self.path.display()
+                )
+            })?;
+
+        // not a local file, e.g. a built in module.
+        let path = match url.to_file_path() {
+            Ok(path) if url.scheme() == "file" => path,
+            _ => return Ok(url),
+        };
+
+        let path_str = path.to_string_lossy();
+        let candidates: Vec<PathBuf> = std::iter::once(path.clone())
+            .chain(
+                CANDIDATE_SUFFIXES
+                    .iter()
+                    .map(|suffix| PathBuf::from(format!("{}{}", path_str, suffix))),
+            )
+            .collect();
+
+        match candidates.iter().find(|candidate| candidate.is_file()) {
+            Some(found) => Url::from_file_path(found)
+                .map_err(|()| anyhow!("Could not get URL from {}", found.display())),
+            None => {
+                let tried: Vec<String> = candidates
+                    .iter()
+                    .map(|candidate| format!("\n  {}", candidate.display()))
+                    .collect();
+
+                bail!(
+                    "Could not resolve {:?} imported from {}, mapped by {} to none of:{}",
+                    specifier,
+                    referrer,
+                    self.path.display(),
+                    tried.concat()
+                )
+            }
+        }
+    }
+}
+
+/// Bare specifiers are the ones that are not URLs or relative / absolute
+/// paths, and so can only be resolved with an import map.
+pub fn is_bare_specifier(specifier: &str) -> bool {
+    !(specifier.starts_with("./")
+        || specifier.starts_with("../")
+        || specifier.starts_with('/')
+        || Url::parse(specifier).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // a project directory with `files`, removed again after `test`.
+    fn with_project(name: &str, files: &[(&str, &str)], test: impl FnOnce(&Path)) {
+        let directory = std::env::temp_dir().join(format!("murmel-{}-{}", name, process::id()));
+        for (file, contents) in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let directory = fs::canonicalize(&directory).unwrap();
+        test(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn resolve(directory: &Path, specifier: &str) -> anyhow::Result<String> {
+        let import_map = ImportMap::for_entrypoint(&directory.join("main.ts"))?.unwrap();
+        let url = import_map.resolve(specifier, "main.ts")?;
+        let path = url.to_file_path().unwrap();
+        Ok(path.strip_prefix(directory).unwrap().display().to_string())
+    }
+
+    #[test]
+    fn names_resolve_to_files_with_candidate_suffixes() {
+        with_project(
+            "import-map-names",
+            &[
+                (
+                    "import_map.json",
+                    r#"{"imports": {"tonal": "./vendor/tonal", "std": "murmel:std"}}"#,
+                ),
+                ("vendor/tonal/index.js", ""),
+            ],
+            |directory| {
+                assert_eq!(
+                    resolve(directory, "tonal").unwrap(),
+                    "vendor/tonal/index.js"
+                );
+
+                let import_map = ImportMap::for_entrypoint(&directory.join("main.ts"))
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    import_map.resolve("std", "main.ts").unwrap().as_str(),
+                    "murmel:std"
+                );
+                assert!(import_map
+                    .resolve("missing", "main.ts")
+                    .unwrap_err()
+                    .to_string()
+                    .contains("not in the import map"));
+            },
+        );
+    }
+
+    #[test]
+    fn trailing_slash_keys_map_prefixes() {
+        with_project(
+            "import-map-prefixes",
+            &[
+                (
+                    "import_map.json",
+                    r#"{"imports": {"lib/": "./vendor/lib/", "lib/drums/": "./drums/"}}"#,
+                ),
+                ("vendor/lib/chords.ts", ""),
+                ("drums/kick.ts", ""),
+            ],
+            |directory| {
+                assert_eq!(
+                    resolve(directory, "lib/chords").unwrap(),
+                    "vendor/lib/chords.ts"
+                );
+                // the longest key wins.
+                assert_eq!(
+                    resolve(directory, "lib/drums/kick").unwrap(),
+                    "drums/kick.ts"
+                );
+                assert!(resolve(directory, "lib/missing").is_err());
+            },
+        );
+    }
+
+    #[test]
+    fn keys_and_targets_must_agree_on_trailing_slashes() {
+        with_project(
+            "import-map-slashes",
+            &[(
+                "import_map.json",
+                r#"{"imports": {"lib/": "./vendor/lib"}}"#,
+            )],
+            |directory| {
+                let error = ImportMap::for_entrypoint(&directory.join("main.ts")).unwrap_err();
+                assert!(format!("{:#}", error).contains("must either both or neither end with /"));
+            },
+        );
+    }
+
+    #[test]
+    fn invalid_import_maps_are_rejected() {
+        let invalid = [
+            r#"{"imports": {"tonal": }"#,
+            r#"{"imports": ["tonal"]}"#,
+            r#"{"imports": {}, "scopes": {"./vendor/": {"tonal": "./tonal.ts"}}}"#,
+        ];
+
+        for (index, contents) in invalid.iter().enumerate() {
+            with_project(
+                &format!("import-map-invalid-{}", index),
+                &[("import_map.json", contents)],
+                |directory| {
+                    let error = ImportMap::for_entrypoint(&directory.join("main.ts")).unwrap_err();
+                    assert!(error.to_string().contains("Could not load import map"));
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn the_project_file_points_at_the_import_map() {
+        with_project(
+            "import-map-project",
+            &[
+                ("murmel.json", r#"{"importMap": "./maps/imports.json"}"#),
+                (
+                    "maps/imports.json",
+                    r#"{"imports": {"song": "../song.ts"}}"#,
+                ),
+                ("import_map.json", r#"{"imports": {}}"#),
+                ("song.ts", ""),
+            ],
+            |directory| assert_eq!(resolve(directory, "song").unwrap(), "song.ts"),
+        );
+
+        with_project("import-map-none", &[("song.ts", "")], |directory| {
+            assert!(ImportMap::for_entrypoint(&directory.join("main.ts"))
+                .unwrap()
+                .is_none());
+        });
+    }
+}
@@ -0,0 +1,48 @@
+;((window) => {
+    const ops = window.Deno.core.ops
+
+    const formatArg = (arg) => {
+        if (typeof arg === 'string') {
+            return arg
+        }
+
+        if (arg instanceof Error) {
+            return arg.stack ?? String(arg)
+        }
+
+        try {
+            return JSON.stringify(arg) ?? String(arg)
+        } catch {
+            return String(arg)
+        }
+    }
+
+    // file, line and column of whoever called the console method, in the
+    // transpiled code. op_murmel_log maps them back to the source.
+    const callerLocation = () => {
+        const frames = (new Error().stack ?? '').split('\n')
+        // frames[0] is the message, [1] this function, [2] the console
+        // method and [3] the caller.
+        const match = /([^\s(]+):(\d+):(\d+)\)?$/.exec(frames[3] ?? '')
+        return match
+            ? [match[1], Number(match[2]), Number(match[3])]
+            : ['', 0, 0]
+    }
+
+    const logger =
+        (level) =>
+        (...args) =>
+            ops.op_murmel_log(
+                level,
+                ...callerLocation(),
+                args.map(formatArg).join(' ')
+            )
+
+    window.console = {
+        log: logger('info'),
+        info: logger('info'),
+        warn: logger('warn'),
+        error: logger('error'),
+        debug: logger('debug'),
+    }
+})(globalThis)
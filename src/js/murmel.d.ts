@@ -0,0 +1,88 @@
+// Declarations for the globals set up by murmel.js and console.js, and the
+// types `murmel:std` exports besides the generated ones.
+
+// `tick` is the tick playing when the event arrived. It counts the same
+// ticks as `murmel.transport.tick`, which is usually a bit ahead as events
+// are generated before they play.
+export type MidiInputEvent =
+    | {
+          type: 'NoteOn' | 'NoteOff'
+          tick: number
+          channel: number
+          note: number
+          velocity: number
+      }
+    | {
+          type: 'ControlChange'
+          tick: number
+          channel: number
+          controller: number
+          value: number
+      }
+
+// Shape of the JSON module produced when importing a `.mid` file, e.g.
+// `import clip from './clip.mid' assert { type: 'json' }`. Ticks are absolute
+// and in murmel's resolution.
+export type MidiFileEvent = {
+    tick: number
+    type: 'NoteOn' | 'NoteOff'
+    note: number
+    velocity: number
+    channel: number
+}
+
+export type MidiFile = {
+    tracks: MidiFileEvent[][]
+}
+
+// Position of the event the generator is about to yield. Events are
+// generated ahead of playback, so this is usually a bit later than what is
+// playing right now.
+export type Transport = {
+    readonly tick: number
+    // 0-based, following TimeSignature events. A time signature change
+    // starts a new bar.
+    readonly bar: number
+    // 0-based, within the bar, in the note value of the denominator.
+    readonly beat: number
+    readonly numerator: number
+    readonly denominator: number
+    readonly bpm: number
+    // Whether the player is currently playing.
+    readonly playing: boolean
+    // Time since the start of the script, following the tempo changes.
+    readonly seconds: number
+}
+
+global {
+    const murmel: {
+        readonly transport: Transport
+        // Current value of a parameter set from OSC, MIDI, the keyboard or
+        // stdin, or `defaultValue` if it has not been set.
+        param(name: string, defaultValue?: number): number
+        // MIDI input events received since the previous call.
+        midiIn(): MidiInputEvent[]
+        // Like Math.random(), but from the seed murmel prints at start, so
+        // a take can be reproduced with `--seed`.
+        random(): number
+        // Random integer from `min` up to, but not including, `max`.
+        randomInt(min: number, max: number): number
+        // Resolves after `millis` milliseconds. Note that playback only
+        // waits 500ms for next() of an async generator to settle, and plays
+        // what it has until it does.
+        sleep(millis: number): Promise<void>
+        // Contents of a file, relative paths are relative to where murmel
+        // was started.
+        readTextFile(path: string): Promise<string>
+    }
+
+    // Logged through murmel's logger, with the calling file and its line in
+    // the source.
+    const console: {
+        log(...args: unknown[]): void
+        info(...args: unknown[]): void
+        warn(...args: unknown[]): void
+        error(...args: unknown[]): void
+        debug(...args: unknown[]): void
+    }
+}
@@ -0,0 +1,22 @@
+;((window) => {
+    const core = window.Deno.core
+    const ops = core.ops
+
+    const murmel = {
+        param: (name, defaultValue = 0) =>
+            ops.op_murmel_param(name, defaultValue),
+        midiIn: () => ops.op_murmel_midi_in(),
+        random: () => ops.op_murmel_random(),
+        randomInt: (min, max) =>
+            min + Math.floor(ops.op_murmel_random() * (max - min)),
+        sleep: (millis) => core.opAsync('op_murmel_sleep', millis),
+        readTextFile: (path) => core.opAsync('op_murmel_read_text_file', path),
+    }
+
+    Object.defineProperty(murmel, 'transport', {
+        get: () => Object.freeze(ops.op_murmel_transport()),
+        enumerable: true,
+    })
+
+    window.murmel = murmel
+})(globalThis)
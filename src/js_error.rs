@@ -0,0 +1,184 @@
+use crate::ts_module_loader::SourceMapStore;
+use anyhow::anyhow;
+use deno_core::error::JsError;
+use std::fmt::Write;
+
+// lines of source shown before and after the failing line.
+const CODE_FRAME_CONTEXT_LINES: usize = 2;
+
+/// Replaces a JS exception with a readable report: the exception message, a
+/// code frame of the original source and the stack trace. Positions are
+/// already mapped to the original sources by the runtime. Other errors are
+/// returned as is.
+pub fn render_js_error(error: anyhow::Error, source_maps: &SourceMapStore) -> anyhow::Error {
+    match error.downcast::<JsError>() {
+        Ok(js_error) => anyhow!(format_js_error(&js_error, source_maps)),
+        Err(error) => error,
+    }
+}
+
+fn format_js_error(js_error: &JsError, source_maps: &SourceMapStore) -> String {
+    let mut report = js_error.exception_message.clone();
+
+    // the code frame is shown for the innermost frame that is in a script,
+    // not in the runtime.
+    let script_frame = js_error.frames.iter().find_map(|frame| {
+        match (&frame.file_name, frame.line_number, frame.column_number) {
+            (Some(file_name), Some(line), Some(column)) if file_name.starts_with("file:") => {
+                Some((file_name, line, column))
+            }
+            _ => None,
+        }
+    });
+
+    if let Some((file_name, line, column)) = script_frame {
+        let _ = write!(report, "\n  --> {}:{}:{}", file_name, line, column);
+        report.push_str(&code_frame(source_maps, file_name, line, column));
+    }
+
+    for frame in js_error.frames.iter() {
+        let location = match (&frame.file_name, frame.line_number, frame.column_number) {
+            (Some(file_name), Some(line), Some(column)) => {
+                format!("{}:{}:{}", file_name, line, column)
+            }
+            (Some(file_name), _, _) => file_name.clone(),
+            _ => "<unknown>".to_string(),
+        };
+
+        match &frame.function_name {
+            Some(function_name) => {
+                let _ = write!(report, "\n    at {} ({})", function_name, location);
+            }
+            None => {
+                let _ = write!(report, "\n    at {}", location);
+            }
+        }
+    }
+
+    report
+}
+
+// `line` and `column` are 1-based, as in JS stack frames.
+fn code_frame(source_maps: &SourceMapStore, file_name: &str, line: i64, column: i64) -> String {
+    let line = match usize::try_from(line) {
+        Ok(line) if line > 0 => line,
+        _ => return String::new(),
+    };
+
+    let first_line = line.saturating_sub(CODE_FRAME_CONTEXT_LINES).max(1);
+    let last_line = line + CODE_FRAME_CONTEXT_LINES;
+    let gutter_width = last_line.to_string().len();
+    let mut frame = String::new();
+
+    for line_number in first_line..=last_line {
+        let source_line = match source_maps.source_line(file_name, line_number - 1) {
+            Some(source_line) => source_line,
+            None => break,
+        };
+
+        let _ = write!(
+            frame,
+            "\n{:>width$} | {}",
+            line_number,
+            source_line,
+            width = gutter_width
+        );
+
+        if line_number == line {
+            let padding = usize::try_from(column - 1).unwrap_or(0);
+            let _ = write!(
+                frame,
+                "\n{:>width$} | {}^",
+                "",
+                " ".repeat(padding),
+                width = gutter_width
+            );
+        }
+    }
+
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deno_core::{error::JsStackFrame, ModuleSpecifier};
+
+    fn frame(function_name: Option<&str>, file_name: &str, line: i64, column: i64) -> JsStackFrame {
+        JsStackFrame {
+            function_name: function_name.map(str::to_string),
+            ..JsStackFrame::from_location(Some(file_name.to_string()), Some(line), Some(column))
+        }
+    }
+
+    fn js_error(frames: Vec<JsStackFrame>) -> JsError {
+        JsError {
+            name: Some("TypeError".to_string()),
+            message: Some("x is undefined".to_string()),
+            stack: None,
+            cause: None,
+            exception_message: "Uncaught TypeError: x is undefined".to_string(),
+            frames,
+            source_line: None,
+            source_line_frame_index: None,
+            aggregated: None,
+        }
+    }
+
+    fn source_maps() -> SourceMapStore {
+        let source_maps = SourceMapStore::default();
+        source_maps.insert(
+            &ModuleSpecifier::parse("file:///main.ts").unwrap(),
+            "const a = 1\nconst b = 2\nx.play()\nconst c = 3\nconst d = 4\nconst e = 5".to_string(),
+            None,
+        );
+        source_maps
+    }
+
+    #[test]
+    fn reports_show_the_source_around_the_innermost_script_frame() {
+        let error = js_error(vec![
+            frame(Some("op"), "ext:core.js", 10, 3),
+            frame(Some("play"), "file:///main.ts", 3, 3),
+            frame(None, "file:///main.ts", 6, 1),
+        ]);
+
+        assert_eq!(
+            format_js_error(&error, &source_maps()),
+            "Uncaught TypeError: x is undefined\n\
+             \x20 --> file:///main.ts:3:3\n\
+             1 | const a = 1\n\
+             2 | const b = 2\n\
+             3 | x.play()\n\
+             \x20 |   ^\n\
+             4 | const c = 3\n\
+             5 | const d = 4\n\
+             \x20   at op (ext:core.js:10:3)\n\
+             \x20   at play (file:///main.ts:3:3)\n\
+             \x20   at file:///main.ts:6:1"
+        );
+    }
+
+    #[test]
+    fn reports_without_sources_only_have_the_stack() {
+        let error = js_error(vec![
+            frame(Some("play"), "file:///song.ts", 1, 1),
+            JsStackFrame::from_location(None, None, None),
+        ]);
+
+        assert_eq!(
+            format_js_error(&error, &source_maps()),
+            "Uncaught TypeError: x is undefined\n\
+             \x20 --> file:///song.ts:1:1\n\
+             \x20   at play (file:///song.ts:1:1)\n\
+             \x20   at <unknown>"
+        );
+    }
+
+    #[test]
+    fn other_errors_are_returned_as_is() {
+        let error = render_js_error(anyhow!("Could not read main.ts"), &source_maps());
+
+        assert_eq!(error.to_string(), "Could not read main.ts");
+    }
+}
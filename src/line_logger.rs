@@ -0,0 +1,37 @@
+use std::io::{stdout, Write};
+
+use log::{Log, Metadata, Record, SetLoggerError};
+
+/// Logger for headless mode: plain lines without colors or cursor movement,
+/// so the output can be redirected to a file or journal.
+pub struct LineLogger;
+
+impl LineLogger {
+    pub fn init() -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(LineLogger))
+    }
+}
+
+impl Log for LineLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut stdout = stdout().lock();
+
+        for line in record.args().to_string().split('\n') {
+            let _ = writeln!(
+                stdout,
+                "{} {} {}",
+                record.module_path().unwrap_or(""),
+                record.level(),
+                line
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = stdout().flush();
+    }
+}
@@ -1,20 +1,52 @@
+mod builtin_modules;
+mod cli;
 mod crossterm_raw_logger;
 mod event;
 mod event_coordinator;
 mod event_generator;
 mod event_generator_thread;
+mod event_schema;
+mod groove;
+mod headless;
+mod import_map;
+mod js_error;
+mod line_logger;
+mod midi_file;
+mod midi_input;
+mod ops;
+mod osc_server;
+mod params;
 mod player;
+mod position;
+mod recorder;
+mod snapshot;
+mod tempo;
+mod transpile_cache;
 mod ts_module_loader;
+mod type_declarations;
 
+use crate::cli::{CliArgs, CliCommand};
 use crate::crossterm_raw_logger::CrosstermRawLogger;
-use crate::event_coordinator::new_event_coordinator;
-use crate::player::new_player_actor;
+use crate::event_coordinator::{new_event_coordinator, EventCoordinatorActorHandle};
+use crate::event_generator::EventGeneratorConfig;
+use crate::line_logger::LineLogger;
+use crate::midi_input::{connect_midi_input, MidiInputBuffer};
+use crate::ops::OpsContext;
+use crate::osc_server::start_osc_server;
+use crate::params::ParamStore;
+use crate::player::{new_player_actor, PlayerActorHandle, SharedTransportStatus};
+use crate::transpile_cache::TranspileCache;
 use anyhow::anyhow;
+use crossterm::cursor::MoveToColumn;
 use crossterm::event::{poll, read, Event, KeyCode, KeyModifiers};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use log::{info, LevelFilter};
+use crossterm::execute;
+use crossterm::style::Print;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType};
+use log::{info, warn, LevelFilter};
 use midir::os::unix::VirtualOutput;
 use midir::MidiOutput;
+use std::io::stdout;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use std::{fs, panic};
 
@@ -26,15 +58,31 @@ pub enum UiEvent {
 const ENTRYPOINT: &str = "./sample_scripts/main.ts";
 
 fn main() -> anyhow::Result<()> {
-    enable_raw_mode()?;
+    let cli_args = CliArgs::parse()?;
+
+    if let Some((path, scripts_path)) = &cli_args.create_snapshot {
+        return snapshot::create_snapshot(path, scripts_path);
+    }
+
+    if let CliCommand::Types { output } = &cli_args.command {
+        return type_declarations::write_type_declarations(output.as_deref());
+    }
+
     log::set_max_level(LevelFilter::Info);
+
+    if cli_args.headless {
+        LineLogger::init()?;
+        return run(&cli_args);
+    }
+
+    enable_raw_mode()?;
     CrosstermRawLogger::init()?;
-    let _ = panic::catch_unwind(run);
+    let _ = panic::catch_unwind(|| run(&cli_args));
     disable_raw_mode()?;
     Ok(())
 }
 
-fn run() -> anyhow::Result<()> {
+fn run(cli_args: &CliArgs) -> anyhow::Result<()> {
     info!("Starting...");
 
     let midi_out = MidiOutput::new("murmel")?;
@@ -45,18 +93,144 @@ fn run() -> anyhow::Result<()> {
     info!("Connected to MIDI output");
 
     let entrypoint = fs::canonicalize(ENTRYPOINT)?;
-    let (event_coordinator, event_coordinator_jh) = new_event_coordinator(&entrypoint);
-    let (player, player_jh) = new_player_actor(event_coordinator.clone(), midi_output_connection);
+    let params = ParamStore::new();
+    let midi_input = MidiInputBuffer::new();
+    let random_seed = cli_args.seed.unwrap_or_else(rand::random);
+    info!(
+        "Random seed {}, pass --seed {} to reproduce",
+        random_seed, random_seed
+    );
+    let transport_status = SharedTransportStatus::default();
+    let ops_context = OpsContext {
+        params: params.clone(),
+        midi_input: midi_input.clone(),
+        random_seed,
+        transport_status: transport_status.clone(),
+    };
+    let transpile_cache = TranspileCache::new(cli_args.transpile_cache_dir.clone());
+    let snapshot = if cli_args.no_snapshot {
+        None
+    } else {
+        snapshot::load_snapshot(&entrypoint, &transpile_cache)
+    };
+    let config = EventGeneratorConfig {
+        entrypoint,
+        ops_context,
+        strict: cli_args.strict,
+        transpile_cache,
+        snapshot,
+    };
+    let (event_coordinator, event_coordinator_jh) =
+        new_event_coordinator(config, cli_args.reload_mode);
+    let (player, player_jh) = new_player_actor(
+        event_coordinator.clone(),
+        midi_output_connection,
+        cli_args.record,
+        random_seed,
+        transport_status,
+    );
+    let actors = Actors {
+        player: player.clone(),
+        event_coordinator: event_coordinator.clone(),
+        player_jh: Some(player_jh),
+        event_coordinator_jh: Some(event_coordinator_jh),
+    };
+    if let Some(groove) = &cli_args.groove {
+        player.set_groove(Some(groove.clone()))?;
+    }
+
+    // dropping the connection would close the port.
+    let _midi_input_connection =
+        match connect_midi_input(player.clone(), midi_input, params.clone()) {
+            Ok(connection) => {
+                info!("Connected to MIDI input");
+                Some(connection)
+            }
+            Err(e) => {
+                warn!("Could not connect to MIDI input: {:?}", e);
+                None
+            }
+        };
+
+    if let Some(port) = cli_args.osc_port {
+        start_osc_server(
+            port,
+            player.clone(),
+            event_coordinator.clone(),
+            params.clone(),
+        )?;
+    }
+
+    if cli_args.headless {
+        headless::run_input_loop(&player, &event_coordinator, &params, actors.player_jh())?;
+    } else {
+        run_keyboard_input_loop(&player, &event_coordinator, &params, actors.player_jh())?;
+    }
+
+    /* let's go! */
+
+    actors.join()?;
+    info!("Stopped!");
+
+    Ok(())
+}
+
+// exits the player and the event coordinator when `run` returns early or
+// panics, so the player gets to release its notes before the process ends.
+struct Actors {
+    player: PlayerActorHandle,
+    event_coordinator: EventCoordinatorActorHandle,
+    player_jh: Option<JoinHandle<anyhow::Result<()>>>,
+    event_coordinator_jh: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Actors {
+    fn player_jh(&self) -> &JoinHandle<anyhow::Result<()>> {
+        self.player_jh.as_ref().unwrap()
+    }
+
+    fn join(mut self) -> anyhow::Result<()> {
+        self.player_jh.take().unwrap().join().unwrap()?;
+        self.event_coordinator_jh.take().unwrap().join().unwrap()?;
+        Ok(())
+    }
+}
 
+impl Drop for Actors {
+    fn drop(&mut self) {
+        if self.player_jh.is_none() && self.event_coordinator_jh.is_none() {
+            return;
+        }
+
+        warn!("Stopping the player");
+        let _ = self.player.exit();
+        let _ = self.event_coordinator.exit();
+
+        for jh in [self.player_jh.take(), self.event_coordinator_jh.take()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = jh.join();
+        }
+    }
+}
+
+fn run_keyboard_input_loop(
+    player: &PlayerActorHandle,
+    event_coordinator: &EventCoordinatorActorHandle,
+    params: &ParamStore,
+    player_jh: &JoinHandle<anyhow::Result<()>>,
+) -> anyhow::Result<()> {
     info!("Press \"p\" to start playing!");
+    info!("Press \":\" to set a parameter.");
 
     while !player_jh.is_finished() {
         if !poll(Duration::from_millis(100))? {
             continue;
         }
 
-        match read()? {
-            Event::Key(event) => match event.code {
+        if let Event::Key(event) = read()? {
+            match event.code {
                 KeyCode::Char('q') => {
                     player.exit()?;
                     event_coordinator.exit()?;
@@ -70,7 +244,7 @@ fn run() -> anyhow::Result<()> {
                 }
 
                 KeyCode::Char('r') => {
-                    event_coordinator.reload_from_next_marker()?;
+                    event_coordinator.reload()?;
                 }
 
                 KeyCode::Char('p') => {
@@ -81,17 +255,71 @@ fn run() -> anyhow::Result<()> {
                     player.stop()?;
                 }
 
+                KeyCode::Char('m') => {
+                    player.toggle_recording()?;
+                }
+
+                KeyCode::Char('x') | KeyCode::Esc => {
+                    player.panic()?;
+                }
+
+                KeyCode::Char(':') => {
+                    if let Some(line) = read_prompt("param <name> <value>: ")? {
+                        match parse_param(&line) {
+                            Some((name, value)) => {
+                                info!("Setting parameter {} to {}", name, value);
+                                params.set(name, value);
+                            }
+                            None => warn!("Expected <name> <value>, got {:?}", line),
+                        }
+                    }
+                }
+
                 _ => (),
-            },
-            _ => (),
-        };
+            }
+        }
     }
 
-    /* let's go! */
+    Ok(())
+}
 
-    player_jh.join().unwrap()?;
-    event_coordinator_jh.join().unwrap()?;
-    info!("Stopped!");
+// reads a line typed after `prompt`, `None` if it was cancelled with Esc or
+// ctrl-c. Keys are not handled as commands meanwhile.
+fn read_prompt(prompt: &str) -> anyhow::Result<Option<String>> {
+    let mut line = String::new();
 
-    Ok(())
+    let line = loop {
+        execute!(
+            stdout(),
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(prompt),
+            Print(&line)
+        )?;
+
+        if let Event::Key(event) = read()? {
+            match event.code {
+                KeyCode::Enter => break Some(line),
+                KeyCode::Esc => break None,
+                KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break None
+                }
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Char(c) => line.push(c),
+                _ => (),
+            }
+        }
+    };
+
+    execute!(stdout(), MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+    Ok(line)
+}
+
+fn parse_param(line: &str) -> Option<(&str, f64)> {
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [name, value] => Some((name, value.parse().ok()?)),
+        _ => None,
+    }
 }
@@ -0,0 +1,201 @@
+use crate::event::{Ticks, TICKS_PER_BEAT};
+use anyhow::{anyhow, bail};
+use deno_core::serde_json;
+use midly::{MidiMessage, Smf, Timing, TrackEventKind};
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct MidiFile {
+    pub tracks: Vec<Vec<MidiFileEvent>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MidiFileEvent {
+    pub tick: Ticks,
+    #[serde(rename = "type")]
+    pub event_type: MidiFileEventType,
+    pub note: u8,
+    pub velocity: u8,
+    pub channel: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub enum MidiFileEventType {
+    NoteOn,
+    NoteOff,
+}
+
+impl MidiFile {
+    /// Parses a Standard MIDI File, keeping only note events. Event ticks are
+    /// absolute positions from the start of the track, converted to murmel's
+    /// resolution of `TICKS_PER_BEAT`.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<MidiFile> {
+        let smf = Smf::parse(bytes)?;
+
+        let file_ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(ticks_per_beat) => u64::from(ticks_per_beat.as_int()),
+            Timing::Timecode(_, _) => bail!("MIDI files with timecode timing are not supported"),
+        };
+
+        if file_ticks_per_beat == 0 {
+            bail!("MIDI file has zero ticks per beat");
+        }
+
+        let mut tracks = vec![];
+
+        for track in smf.tracks.iter() {
+            let mut file_tick: u64 = 0;
+            let mut events = vec![];
+
+            for track_event in track {
+                file_tick += u64::from(track_event.delta.as_int());
+
+                let (channel, message) = match track_event.kind {
+                    TrackEventKind::Midi { channel, message } => (channel.as_int(), message),
+                    _ => continue,
+                };
+
+                let (event_type, note, velocity) = match message {
+                    // note on with zero velocity is a note off by convention.
+                    MidiMessage::NoteOn { key, vel } if vel.as_int() == 0 => {
+                        (MidiFileEventType::NoteOff, key.as_int(), 0)
+                    }
+                    MidiMessage::NoteOn { key, vel } => {
+                        (MidiFileEventType::NoteOn, key.as_int(), vel.as_int())
+                    }
+                    MidiMessage::NoteOff { key, vel } => {
+                        (MidiFileEventType::NoteOff, key.as_int(), vel.as_int())
+                    }
+                    _ => continue,
+                };
+
+                let tick = file_tick * u64::from(TICKS_PER_BEAT) / file_ticks_per_beat;
+
+                events.push(MidiFileEvent {
+                    tick: Ticks::try_from(tick).map_err(|_| anyhow!("MIDI file is too long"))?,
+                    event_type,
+                    note,
+                    velocity,
+                    channel,
+                });
+            }
+
+            tracks.push(events);
+        }
+
+        Ok(MidiFile { tracks })
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::{
+        num::{u15, u24, u28, u4, u7},
+        Format, Header, MetaMessage, TrackEvent,
+    };
+
+    fn midi(delta: u32, message: MidiMessage) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(2),
+                message,
+            },
+        }
+    }
+
+    fn meta(delta: u32, message: MetaMessage<'static>) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Meta(message),
+        }
+    }
+
+    fn file_with(ticks_per_beat: u16, track: Vec<TrackEvent<'static>>) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(ticks_per_beat)),
+        ));
+        smf.tracks.push(track);
+
+        let mut bytes = vec![];
+        smf.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn note_on(delta: u32, key: u8, vel: u8) -> TrackEvent<'static> {
+        midi(
+            delta,
+            MidiMessage::NoteOn {
+                key: u7::new(key),
+                vel: u7::new(vel),
+            },
+        )
+    }
+
+    #[test]
+    fn rescales_ticks_to_murmel_resolution() {
+        let bytes = file_with(
+            96,
+            vec![
+                note_on(0, 60, 100),
+                midi(
+                    48,
+                    MidiMessage::NoteOff {
+                        key: u7::new(60),
+                        vel: u7::new(64),
+                    },
+                ),
+                note_on(48, 62, 90),
+            ],
+        );
+
+        let file = MidiFile::parse(&bytes).unwrap();
+        let ticks: Vec<Ticks> = file.tracks[0].iter().map(|event| event.tick).collect();
+
+        assert_eq!(ticks, [0, TICKS_PER_BEAT / 2, TICKS_PER_BEAT]);
+        assert_eq!(file.tracks[0][0].channel, 2);
+        assert_eq!(file.tracks[0][1].velocity, 64);
+    }
+
+    #[test]
+    fn note_on_with_zero_velocity_is_a_note_off() {
+        let bytes = file_with(480, vec![note_on(0, 60, 100), note_on(480, 60, 0)]);
+
+        let file = MidiFile::parse(&bytes).unwrap();
+
+        assert!(matches!(
+            file.tracks[0][1].event_type,
+            MidiFileEventType::NoteOff
+        ));
+        assert_eq!(file.tracks[0][1].velocity, 0);
+    }
+
+    #[test]
+    fn meta_events_are_skipped_but_count_towards_ticks() {
+        let bytes = file_with(
+            480,
+            vec![
+                meta(0, MetaMessage::Tempo(u24::new(500_000))),
+                meta(0, MetaMessage::TimeSignature(6, 3, 24, 8)),
+                meta(240, MetaMessage::Tempo(u24::new(400_000))),
+                note_on(240, 60, 100),
+            ],
+        );
+
+        let file = MidiFile::parse(&bytes).unwrap();
+
+        assert_eq!(file.tracks[0].len(), 1);
+        assert_eq!(file.tracks[0][0].tick, TICKS_PER_BEAT);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(MidiFile::parse(b"not a midi file").is_err());
+    }
+}
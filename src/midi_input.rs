@@ -0,0 +1,140 @@
+use crate::{params::ParamStore, player::PlayerActorHandle};
+use anyhow::anyhow;
+use log::debug;
+use midir::{os::unix::VirtualInput, MidiInput, MidiInputConnection};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+// if a script never pulls incoming events, only keep the latest ones.
+const MAX_BUFFERED_EVENTS: usize = 1024;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum MidiInputEvent {
+    NoteOn {
+        tick: u64,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        tick: u64,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        tick: u64,
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+}
+
+/// Incoming MIDI events waiting to be pulled by a script with
+/// `murmel.midiIn()`.
+#[derive(Clone, Default)]
+pub struct MidiInputBuffer {
+    events: Arc<Mutex<VecDeque<MidiInputEvent>>>,
+}
+
+impl MidiInputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, event: MidiInputEvent) {
+        let mut events = self.events.lock().unwrap();
+
+        if events.len() >= MAX_BUFFERED_EVENTS {
+            events.pop_front();
+        }
+
+        events.push_back(event);
+    }
+
+    pub fn drain(&self) -> Vec<MidiInputEvent> {
+        self.events.lock().unwrap().drain(..).collect()
+    }
+}
+
+/// Opens a virtual MIDI input port. Incoming notes and control changes are
+/// timestamped with the tick playing when they arrive and buffered for
+/// scripts. Control changes also set the parameter `cc<controller>` to
+/// the value scaled to 0..1.
+///
+/// The port stays open for as long as the returned connection is alive.
+pub fn connect_midi_input(
+    player: PlayerActorHandle,
+    midi_input_buffer: MidiInputBuffer,
+    params: ParamStore,
+) -> anyhow::Result<MidiInputConnection<()>> {
+    let midi_in = MidiInput::new("murmel")?;
+
+    midi_in
+        .create_virtual(
+            "Virtual input",
+            move |_, msg, _| {
+                let tick = player.transport_status().tick_at(Instant::now());
+
+                let event = match parse_message(tick, msg) {
+                    Some(event) => event,
+                    None => return,
+                };
+
+                debug!("MIDI input: {:?}", event);
+
+                if let MidiInputEvent::ControlChange {
+                    controller, value, ..
+                } = event
+                {
+                    params.set(&format!("cc{}", controller), f64::from(value) / 127.0);
+                }
+
+                midi_input_buffer.push(event);
+            },
+            (),
+        )
+        .map_err(|e| anyhow!("Could not create midi input port: {:?}", e))
+}
+
+fn parse_message(tick: u64, msg: &[u8]) -> Option<MidiInputEvent> {
+    let (status, data1, data2) = match msg {
+        [status, data1, data2] => (*status, *data1, *data2),
+        _ => return None,
+    };
+    let channel = status & 0x0F;
+
+    match status & 0xF0 {
+        // note on with zero velocity is a note off by convention.
+        0x90 if data2 == 0 => Some(MidiInputEvent::NoteOff {
+            tick,
+            channel,
+            note: data1,
+            velocity: 0,
+        }),
+        0x90 => Some(MidiInputEvent::NoteOn {
+            tick,
+            channel,
+            note: data1,
+            velocity: data2,
+        }),
+        0x80 => Some(MidiInputEvent::NoteOff {
+            tick,
+            channel,
+            note: data1,
+            velocity: data2,
+        }),
+        0xB0 => Some(MidiInputEvent::ControlChange {
+            tick,
+            channel,
+            controller: data1,
+            value: data2,
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    midi_input::{MidiInputBuffer, MidiInputEvent},
+    params::ParamStore,
+    player::SharedTransportStatus,
+    position::{Position, TransportInfo},
+    ts_module_loader::SourceMapStore,
+};
+use deno_core::{error::AnyError, include_js_files, op, Extension, OpState};
+use log::{log, Level};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::time::Duration;
+
+/// State shared with the ops of every event generator runtime.
+#[derive(Clone)]
+pub struct OpsContext {
+    pub params: ParamStore,
+    pub midi_input: MidiInputBuffer,
+    /// Every runtime starts from this seed, so a reload plays the same random
+    /// choices again.
+    pub random_seed: u64,
+    pub transport_status: SharedTransportStatus,
+}
+
+/// The `murmel` global available to scripts, and the ops backing it.
+pub fn murmel_extension(ops_context: OpsContext) -> Extension {
+    Extension::builder()
+        .js(include_js_files!(
+            prefix "murmel:runtime",
+            "js/murmel.js",
+            "js/console.js",
+        ))
+        .ops(vec![
+            op_murmel_param::decl(),
+            op_murmel_midi_in::decl(),
+            op_murmel_log::decl(),
+            op_murmel_random::decl(),
+            op_murmel_transport::decl(),
+            op_murmel_sleep::decl(),
+            op_murmel_read_text_file::decl(),
+        ])
+        .state(move |state| {
+            state.put(ops_context.params.clone());
+            state.put(ops_context.midi_input.clone());
+            // ChaCha8 rather than `StdRng`, its output is stable across rand
+            // versions.
+            state.put(ChaCha8Rng::seed_from_u64(ops_context.random_seed));
+            state.put(ops_context.transport_status.clone());
+            // replaced by the generator before each next().
+            state.put(Position::default());
+            Ok(())
+        })
+        .build()
+}
+
+#[op]
+fn op_murmel_param(state: &mut OpState, name: String, default: f64) -> f64 {
+    state.borrow::<ParamStore>().get(&name).unwrap_or(default)
+}
+
+#[op]
+fn op_murmel_midi_in(state: &mut OpState) -> Vec<MidiInputEvent> {
+    state.borrow::<MidiInputBuffer>().drain()
+}
+
+// `file_name`, `line` and `column` are the caller's position in the
+// transpiled code, logged as the file name and line of the source.
+#[op]
+fn op_murmel_log(
+    state: &mut OpState,
+    level: String,
+    file_name: String,
+    line: u32,
+    column: u32,
+    message: String,
+) {
+    let level = match level.as_str() {
+        "error" => Level::Error,
+        "warn" => Level::Warn,
+        "debug" => Level::Debug,
+        _ => Level::Info,
+    };
+
+    if file_name.is_empty() {
+        log!(level, "<unknown> {}", message);
+        return;
+    }
+
+    // not there while a snapshot is made.
+    let line = state
+        .try_borrow::<SourceMapStore>()
+        .and_then(|source_maps| source_maps.original_position(&file_name, line, column))
+        .map_or(line, |(line, _)| line);
+    let name = file_name.rsplit('/').next().unwrap_or(&file_name);
+
+    log!(level, "{}:{} {}", name, line, message);
+}
+
+#[op]
+fn op_murmel_random(state: &mut OpState) -> f64 {
+    state.borrow_mut::<ChaCha8Rng>().gen()
+}
+
+#[op]
+fn op_murmel_transport(state: &mut OpState) -> TransportInfo {
+    let playing = state
+        .borrow::<SharedTransportStatus>()
+        .lock()
+        .unwrap()
+        .playing;
+    state.borrow::<Position>().transport_info(playing)
+}
+
+#[op]
+async fn op_murmel_sleep(millis: u64) -> Result<(), AnyError> {
+    tokio::time::sleep(Duration::from_millis(millis)).await;
+    Ok(())
+}
+
+#[op]
+async fn op_murmel_read_text_file(path: String) -> Result<String, AnyError> {
+    Ok(tokio::fs::read_to_string(path).await?)
+}
@@ -0,0 +1,196 @@
+use crate::{
+    crossterm_raw_logger::LogErr,
+    event::{MAX_BPM, MIN_BPM},
+    event_coordinator::EventCoordinatorActorHandle,
+    groove::Groove,
+    params::ParamStore,
+    player::PlayerActorHandle,
+};
+use anyhow::{anyhow, bail};
+use log::{debug, info, warn};
+use rosc::{OscMessage, OscPacket, OscType};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread::spawn,
+};
+
+const ADDRESS_PREFIX: &str = "/murmel/";
+
+// big enough for any reasonable OSC packet.
+const BUFFER_SIZE: usize = 65536;
+
+// `/murmel/swing <percent> [<subdivision>]` swings sixteenth notes without a
+// subdivision.
+const SWING_SUBDIVISION: u64 = 16;
+const STRAIGHT_PERCENT: f64 = 50.0;
+
+struct OscServer {
+    socket: UdpSocket,
+    player: PlayerActorHandle,
+    event_coordinator: EventCoordinatorActorHandle,
+    params: ParamStore,
+}
+
+impl OscServer {
+    fn run(self) {
+        let mut buf = [0u8; BUFFER_SIZE];
+
+        loop {
+            let (size, sender) = match self.socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(e) => {
+                    warn!("Could not receive OSC packet: {:?}", e);
+                    continue;
+                }
+            };
+
+            match rosc::decoder::decode_udp(&buf[..size]) {
+                Ok((_, packet)) => self.handle_packet(packet, sender),
+                Err(e) => warn!("Could not decode OSC packet: {:?}", e),
+            }
+        }
+    }
+
+    fn handle_packet(&self, packet: OscPacket, sender: SocketAddr) {
+        match packet {
+            OscPacket::Message(msg) => {
+                debug!("Received OSC message {:?}", msg);
+
+                self.handle_message(&msg, sender)
+                    .map_err(|e| e.context(format!("Could not handle OSC message {}", msg.addr)))
+                    .log_err();
+            }
+
+            OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    self.handle_packet(packet, sender);
+                }
+            }
+        }
+    }
+
+    fn handle_message(&self, msg: &OscMessage, sender: SocketAddr) -> anyhow::Result<()> {
+        let command = msg
+            .addr
+            .strip_prefix(ADDRESS_PREFIX)
+            .ok_or_else(|| anyhow!("Unknown address"))?;
+
+        match command {
+            "play" => self.player.play(),
+            "stop" => self.player.stop(),
+            "reload" => self.event_coordinator.reload(),
+            "bpm" => {
+                let bpm = float_arg(msg)?;
+                if !(MIN_BPM..=MAX_BPM).contains(&bpm) {
+                    bail!("BPM {} out of range", bpm);
+                }
+                self.player.set_bpm(bpm)
+            }
+            "swing" => {
+                let percent = msg.args.first().map(number).transpose()?;
+                let subdivision = match msg.args.get(1).map(number).transpose()? {
+                    Some(n) if n >= 1.0 && n.fract() == 0.0 => n as u64,
+                    Some(n) => bail!("Expected a subdivision like 8 or 16, got {}", n),
+                    None => SWING_SUBDIVISION,
+                };
+
+                // straight, or no argument, clears the groove.
+                match percent {
+                    Some(percent) if percent != STRAIGHT_PERCENT => self
+                        .player
+                        .set_groove(Some(Groove::swing(percent, subdivision)?)),
+                    _ => self.player.set_groove(None),
+                }
+            }
+            "status" => self.send_status(sender),
+            "panic" => self.player.panic(),
+            _ => match command.strip_prefix("param/") {
+                Some(name) => {
+                    let value = float_arg(msg)?;
+                    self.params.set(name, value);
+                    Ok(())
+                }
+                None => bail!("Unknown address"),
+            },
+        }
+    }
+
+    fn send_status(&self, recipient: SocketAddr) -> anyhow::Result<()> {
+        let status = self.player.transport_status();
+        let reply = OscPacket::Message(OscMessage {
+            addr: format!("{}status", ADDRESS_PREFIX),
+            args: vec![
+                OscType::String(if status.playing { "playing" } else { "stopped" }.to_string()),
+                OscType::Double(status.bpm),
+                OscType::String(status.position.to_string()),
+            ],
+        });
+
+        let buf = rosc::encoder::encode(&reply)?;
+        self.socket.send_to(&buf, recipient)?;
+        Ok(())
+    }
+}
+
+fn float_arg(msg: &OscMessage) -> anyhow::Result<f64> {
+    match msg.args.first() {
+        Some(arg) => number(arg),
+        None => bail!("Expected a number argument"),
+    }
+}
+
+fn number(arg: &OscType) -> anyhow::Result<f64> {
+    match arg {
+        OscType::Float(f) => Ok(f64::from(*f)),
+        OscType::Double(d) => Ok(*d),
+        OscType::Int(i) => Ok(f64::from(*i)),
+        other => bail!("Expected a number argument, got {:?}", other),
+    }
+}
+
+/// Starts listening for OSC messages on the given UDP port.
+pub fn start_osc_server(
+    port: u16,
+    player: PlayerActorHandle,
+    event_coordinator: EventCoordinatorActorHandle,
+    params: ParamStore,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+
+    info!("Listening for OSC messages on UDP port {}", port);
+
+    let server = OscServer {
+        socket,
+        player,
+        event_coordinator,
+        params,
+    };
+
+    spawn(move || server.run());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(args: Vec<OscType>) -> OscMessage {
+        OscMessage {
+            addr: format!("{}bpm", ADDRESS_PREFIX),
+            args,
+        }
+    }
+
+    #[test]
+    fn doubles_keep_their_precision() {
+        let bpm = 120.000_000_1;
+
+        assert_eq!(
+            float_arg(&message(vec![OscType::Double(bpm)])).unwrap(),
+            bpm
+        );
+        assert_eq!(float_arg(&message(vec![OscType::Int(90)])).unwrap(), 90.0);
+        assert!(float_arg(&message(vec![])).is_err());
+    }
+}
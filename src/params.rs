@@ -0,0 +1,25 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Named numeric parameters that control surfaces write to and scripts read
+/// with `murmel.param(name, default)`.
+#[derive(Clone, Default)]
+pub struct ParamStore {
+    values: Arc<Mutex<HashMap<String, f64>>>,
+}
+
+impl ParamStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.values.lock().unwrap().get(name).copied()
+    }
+
+    pub fn set(&self, name: &str, value: f64) {
+        self.values.lock().unwrap().insert(name.to_string(), value);
+    }
+}
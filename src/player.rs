@@ -1,8 +1,17 @@
-use crate::event::{AllNotesOff, Bpm, Event, Ticks, TICKS_PER_BEAT};
+use crate::{
+    event::{Bpm, Event, ALL_NOTES_OFF_CONTROLLER, ALL_SOUND_OFF_CONTROLLER, TICKS_PER_BEAT},
+    groove::Groove,
+    position::{BarBeatTick, BarTracker},
+    recorder::Recorder,
+    tempo::Tempo,
+};
 use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
 use log::{debug, info, warn};
 use midir::MidiOutputConnection;
 use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex},
     thread::{spawn, JoinHandle},
     time::{Duration, Instant},
 };
@@ -11,6 +20,10 @@ use thread_priority::{set_current_thread_priority, ThreadPriority, ThreadPriorit
 pub enum Msg {
     Play,
     Stop,
+    SetBpm(Bpm),
+    SetGroove(Option<Groove>),
+    ToggleRecording,
+    Panic,
     Exit,
 }
 
@@ -19,35 +32,109 @@ pub enum PlayerStatus {
     Playing,
 }
 
-const BEAT_IN_120_BPM: Duration = Duration::from_millis(500);
+/// Snapshot of the player state, readable from other threads.
+#[derive(Clone, Copy, Debug)]
+pub struct TransportStatus {
+    pub playing: bool,
+    pub bpm: Bpm,
+    /// Ticks of the event stream played so far, counted like
+    /// `murmel.transport.tick`. Only moves when the player reaches the next
+    /// events, see `tick_at` for the tick in between.
+    pub tick: u64,
+    /// When the player reached `tick`.
+    pub tick_reached_at: Instant,
+    /// Tick of the events the player is waiting for.
+    pub next_tick: u64,
+    pub position: BarBeatTick,
+}
+
+impl Default for TransportStatus {
+    fn default() -> Self {
+        TransportStatus {
+            playing: false,
+            bpm: Tempo::default().bpm(),
+            tick: 0,
+            tick_reached_at: Instant::now(),
+            next_tick: 0,
+            position: BarBeatTick::default(),
+        }
+    }
+}
+
+impl TransportStatus {
+    /// The tick playing at `now`, counted on from `tick` at the current
+    /// tempo but never past the events the player is waiting for.
+    pub fn tick_at(&self, now: Instant) -> u64 {
+        if !self.playing {
+            return self.tick;
+        }
+
+        let elapsed = now.saturating_duration_since(self.tick_reached_at);
+        let ticks = elapsed.as_secs_f64() * self.bpm / 60.0 * f64::from(TICKS_PER_BEAT);
+
+        (self.tick + ticks as u64).min(self.next_tick.max(self.tick))
+    }
+}
 
-pub struct PlayerActor<T: PlayerEventSource> {
+/// Written by the player, read by the UI, OSC and scripts.
+pub type SharedTransportStatus = Arc<Mutex<TransportStatus>>;
+
+// tempo ramps are recorded as a tempo change every sixteenth note.
+const RAMP_RECORDING_STEP: u64 = TICKS_PER_BEAT as u64 / 4;
+const RECORDING_DIRECTORY: &str = ".";
+
+pub struct PlayerActor<T: PlayerEventSource, C: Clock, M: MidiSink> {
     pub player_event_source: T,
-    pub midi_output_connection: MidiOutputConnection,
+    pub clock: C,
+    pub midi_sink: M,
     pub rx: Receiver<Msg>,
 
     // internal player state
-    current_bpm: Bpm,
-    first_event_instant: Option<Instant>,
-    should_have_elapsed: Duration,
+    // also keeps the time every event is scheduled for.
+    tempo: Tempo,
+    first_event_time: Option<Duration>,
+    played_ticks: u64,
+    bars: BarTracker,
+    groove: Option<Groove>,
+    // (channel, note) of every note on without a note off yet.
+    sounding_notes: BTreeSet<(u8, u8)>,
     player_status: PlayerStatus,
+    recorder: Option<Recorder>,
+    // written to recordings, so they can be reproduced.
+    random_seed: u64,
+    transport_status: SharedTransportStatus,
 }
 
-impl<T: PlayerEventSource> PlayerActor<T> {
+impl<T: PlayerEventSource, C: Clock, M: MidiSink> PlayerActor<T, C, M> {
     pub fn new(
         player_event_source: T,
-        midi_output_connection: MidiOutputConnection,
+        clock: C,
+        midi_sink: M,
         rx: Receiver<Msg>,
+        record: bool,
+        random_seed: u64,
+        transport_status: SharedTransportStatus,
     ) -> Self {
         PlayerActor {
             player_event_source,
-            midi_output_connection,
+            clock,
+            midi_sink,
             rx,
 
-            current_bpm: 120,
-            first_event_instant: None,
-            should_have_elapsed: Duration::ZERO,
+            tempo: Tempo::default(),
+            first_event_time: None,
+            played_ticks: 0,
+            bars: BarTracker::default(),
+            groove: None,
+            sounding_notes: BTreeSet::new(),
             player_status: PlayerStatus::Stopped,
+            recorder: if record {
+                Some(Recorder::new(random_seed, Tempo::default().bpm()))
+            } else {
+                None
+            },
+            random_seed,
+            transport_status,
         }
     }
 
@@ -60,18 +147,42 @@ impl<T: PlayerEventSource> PlayerActor<T> {
                     if !matches!(self.player_status, PlayerStatus::Playing) {
                         info!("Starting playing");
                         self.player_status = PlayerStatus::Playing;
+                        self.update_transport_status();
                     }
                 }
 
-                Ok(Msg::Stop) => {
-                    if !matches!(self.player_status, PlayerStatus::Stopped) {
-                        info!("Stopping playing");
-                        self.first_event_instant = None;
-                        self.should_have_elapsed = Duration::ZERO;
-                        self.player_status = PlayerStatus::Stopped;
+                Ok(Msg::Stop) => self.stop()?,
+
+                Ok(Msg::Panic) => {
+                    info!("Panic, silencing all notes");
+                    self.panic()?;
+                }
+
+                Ok(Msg::SetBpm(bpm)) => {
+                    info!("Setting BPM to {}", bpm);
+                    self.set_bpm(bpm);
+                }
+
+                Ok(Msg::SetGroove(groove)) => {
+                    match &groove {
+                        Some(_) => info!("Setting groove"),
+                        None => info!("Removing groove"),
                     }
+                    self.groove = groove;
                 }
 
+                Ok(Msg::ToggleRecording) => match self.recorder {
+                    Some(_) => {
+                        self.write_recording();
+                        self.recorder = None;
+                        info!("Recording disabled");
+                    }
+                    None => {
+                        self.recorder = Some(Recorder::new(self.random_seed, self.tempo.bpm()));
+                        info!("Recording enabled");
+                    }
+                },
+
                 Err(TryRecvError::Empty) => (),
 
                 Err(TryRecvError::Disconnected) => {
@@ -87,49 +198,122 @@ impl<T: PlayerEventSource> PlayerActor<T> {
                         // TODO: we should wait for a while and see if one
                         // comes. Event generator might just be slow.
                         warn!("No next event available. Stopping.");
-                        self.player_status = PlayerStatus::Stopped
+                        self.player_status = PlayerStatus::Stopped;
+                        self.update_transport_status();
                     }
                 }
             }
         }
 
+        self.release_sounding_notes()?;
+        self.write_recording();
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        if matches!(self.player_status, PlayerStatus::Stopped) {
+            return Ok(());
+        }
+
+        info!(
+            "Stopping playing at {}",
+            self.bars.bar_beat_tick(self.played_ticks)
+        );
+        self.release_sounding_notes()?;
+        self.first_event_time = None;
+        // playing resumes where the events stopped, maybe part way through
+        // a ramp.
+        self.tempo.restart();
+        self.player_status = PlayerStatus::Stopped;
+        self.update_transport_status();
+        self.write_recording();
+
+        Ok(())
+    }
+
+    fn release_sounding_notes(&mut self) -> anyhow::Result<()> {
+        for (channel, note) in std::mem::take(&mut self.sounding_notes) {
+            self.send_to_midi(&[0x80 | channel, note, 0])?;
+        }
+
+        Ok(())
+    }
+
+    // note offs for the tracked notes, then All Notes Off and All Sound Off on
+    // every channel for anything that was missed.
+    fn panic(&mut self) -> anyhow::Result<()> {
+        self.release_sounding_notes()?;
+
+        for channel in 0..16 {
+            self.midi_sink
+                .send(&[0xB0 | channel, ALL_NOTES_OFF_CONTROLLER, 0])?;
+            self.midi_sink
+                .send(&[0xB0 | channel, ALL_SOUND_OFF_CONTROLLER, 0])?;
+        }
+
         Ok(())
     }
 
     fn process_new_event(&mut self, event: Event) -> anyhow::Result<()> {
-        let first_event_instant = self
-            .first_event_instant
-            .get_or_insert(Instant::now())
-            .to_owned();
+        let first_event_time = *self.first_event_time.get_or_insert(self.clock.now());
 
         debug!("Next event: {:?}", event);
 
         match &event {
-            Event::NoteOn(e) => self.send_to_midi(&e.to_midi_msg())?,
+            Event::NoteOn(e) => {
+                let mut msg = e.to_midi_msg();
+                if let Some(groove) = &self.groove {
+                    msg[2] = groove.velocity(self.played_ticks, msg[2]);
+                }
+                self.send_to_midi(&msg)?
+            }
 
             Event::NoteOff(e) => self.send_to_midi(&e.to_midi_msg())?,
 
             Event::AllNotesOff(e) => self.send_to_midi(&e.to_midi_msg())?,
 
-            Event::Print { value } => info!("Print: {}", value),
+            Event::Print { value } => info!(
+                "Print at {}: {}",
+                self.bars.bar_beat_tick(self.played_ticks),
+                value
+            ),
 
             Event::Wait(e) => {
-                let duration = self.ticks_to_duration(e.ticks);
-
-                self.should_have_elapsed += duration;
+                self.advance_tempo(u64::from(e.ticks));
+                self.played_ticks += u64::from(e.ticks);
+                self.transport_status.lock().unwrap().next_tick = self.played_ticks;
 
                 let wait_duration = self
-                    .should_have_elapsed
-                    .checked_sub(first_event_instant.elapsed())
+                    .dispatch_time()
+                    .checked_sub(self.clock.now().saturating_sub(first_event_time))
                     .unwrap_or(Duration::ZERO);
 
                 debug!("Waiting {:?}", wait_duration);
 
                 // TODO: interrupting the thread should be able to interrupt this as well.
-                spin_sleep::sleep(wait_duration)
+                self.clock.sleep(wait_duration);
+
+                self.update_transport_status();
+            }
+
+            Event::ChangeBpm(e) => self.set_bpm(e.bpm),
+
+            Event::TempoRamp(ramp) => {
+                self.tempo.start_ramp(ramp);
+                self.update_transport_status();
             }
 
-            Event::ChangeBpm(e) => self.current_bpm = e.bpm,
+            Event::TimeSignature(time_signature) => {
+                let applied = self
+                    .bars
+                    .set_time_signature(self.played_ticks, *time_signature);
+                // an SMF can only store power of two denominators.
+                if let (true, Some(recorder)) = (applied, &mut self.recorder) {
+                    recorder.record_time_signature(self.tempo.elapsed(), *time_signature);
+                }
+                self.update_transport_status();
+            }
 
             Event::Marker => {}
         }
@@ -138,25 +322,121 @@ impl<T: PlayerEventSource> PlayerActor<T> {
     }
 
     fn send_to_midi(&mut self, msg: &[u8]) -> anyhow::Result<()> {
-        self.midi_output_connection
-            .send(&msg)
-            .map_err(anyhow::Error::msg)
+        match *msg {
+            // a note on with velocity 0 is a note off.
+            [status, note, velocity] if status & 0xF0 == 0x90 && velocity > 0 => {
+                self.sounding_notes.insert((status & 0x0F, note));
+            }
+            [status, note, _] if status & 0xF0 == 0x80 || status & 0xF0 == 0x90 => {
+                self.sounding_notes.remove(&(status & 0x0F, note));
+            }
+            _ => (),
+        }
+
+        let time = self.dispatch_time();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(time, msg);
+        }
+
+        self.midi_sink.send(msg)
+    }
+
+    fn update_transport_status(&self) {
+        let mut transport_status = self.transport_status.lock().unwrap();
+        let playing = matches!(self.player_status, PlayerStatus::Playing);
+        // not on tempo changes, the tick in between would jump back.
+        if transport_status.tick != self.played_ticks || transport_status.playing != playing {
+            transport_status.tick_reached_at = Instant::now();
+        }
+        transport_status.playing = playing;
+        transport_status.bpm = self.tempo.bpm();
+        transport_status.tick = self.played_ticks;
+        transport_status.next_tick = self.played_ticks;
+        transport_status.position = self.bars.bar_beat_tick(self.played_ticks);
     }
 
-    fn ticks_to_duration(&self, ticks: Ticks) -> Duration {
-        let single_tick_duration =
-            (BEAT_IN_120_BPM * 120) / self.current_bpm.into() / TICKS_PER_BEAT;
+    fn write_recording(&mut self) {
+        let recorder = match &mut self.recorder {
+            Some(recorder) if !recorder.is_empty() => recorder,
+            _ => return,
+        };
 
-        single_tick_duration * ticks
+        match recorder.write_to_file(Path::new(RECORDING_DIRECTORY)) {
+            Ok(path) => info!("Recording written to {}", path.to_string_lossy()),
+            Err(e) => warn!("Could not write recording: {:?}", e),
+        }
+    }
+
+    // when events at the current tick are due, moved by the groove.
+    fn dispatch_time(&self) -> Duration {
+        let offset = match &self.groove {
+            Some(groove) => groove.offset_ticks(self.played_ticks),
+            None => 0,
+        };
+        let shift = self.tempo.duration_of(offset.unsigned_abs());
+
+        if offset < 0 {
+            self.tempo.elapsed().saturating_sub(shift)
+        } else {
+            self.tempo.elapsed() + shift
+        }
+    }
+
+    fn set_bpm(&mut self, bpm: Bpm) {
+        self.tempo.set_bpm(bpm);
+        self.record_tempo(bpm);
+        self.update_transport_status();
+    }
+
+    fn record_tempo(&mut self, bpm: Bpm) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_tempo(self.tempo.elapsed(), bpm);
+        }
+    }
+
+    // moves the scheduled time `ticks` ahead. A ramp is followed in steps,
+    // so the recording gets its tempo changes.
+    fn advance_tempo(&mut self, mut ticks: u64) {
+        while let Some(ramp_ticks_left) = self.tempo.ramp_ticks_left() {
+            if ticks == 0 {
+                return;
+            }
+
+            let step = ticks.min(ramp_ticks_left).min(RAMP_RECORDING_STEP);
+            let mut next = self.tempo;
+            next.advance(step);
+            self.record_tempo(average_bpm(step, next.elapsed() - self.tempo.elapsed()));
+            self.tempo = next;
+            ticks -= step;
+        }
+
+        self.record_tempo(self.tempo.bpm());
+        self.tempo.advance(ticks);
     }
 }
 
+// the constant tempo that plays `ticks` in `duration`.
+fn average_bpm(ticks: u64, duration: Duration) -> Bpm {
+    60.0 * ticks as f64 / (f64::from(TICKS_PER_BEAT) * duration.as_secs_f64())
+}
+
 pub fn new_player_actor<T: PlayerEventSource + Send + 'static>(
     player_event_source: T,
     midi_output_connection: MidiOutputConnection,
+    record: bool,
+    random_seed: u64,
+    transport_status: SharedTransportStatus,
 ) -> (PlayerActorHandle, JoinHandle<anyhow::Result<()>>) {
     let (tx, rx) = unbounded();
-    let player = PlayerActor::new(player_event_source, midi_output_connection, rx);
+    let player = PlayerActor::new(
+        player_event_source,
+        SystemClock::new(),
+        midi_output_connection,
+        rx,
+        record,
+        random_seed,
+        transport_status.clone(),
+    );
 
     let jh = spawn(move || -> anyhow::Result<()> {
         debug!("Player thread started");
@@ -171,7 +451,10 @@ pub fn new_player_actor<T: PlayerEventSource + Send + 'static>(
         Ok(())
     });
 
-    let handle = PlayerActorHandle { tx };
+    let handle = PlayerActorHandle {
+        tx,
+        transport_status,
+    };
 
     (handle, jh)
 }
@@ -184,6 +467,7 @@ fn get_player_thread_priority() -> ThreadPriority {
 #[derive(Clone)]
 pub struct PlayerActorHandle {
     tx: Sender<Msg>,
+    transport_status: SharedTransportStatus,
 }
 
 impl PlayerActorHandle {
@@ -197,22 +481,463 @@ impl PlayerActorHandle {
         Ok(())
     }
 
+    pub fn set_bpm(&self, bpm: Bpm) -> anyhow::Result<()> {
+        self.tx.send(Msg::SetBpm(bpm))?;
+        Ok(())
+    }
+
+    /// Applies `groove` to everything played from now on, or plays straight
+    /// with `None`. It's the one groove for all events, they have no track
+    /// to set a groove for.
+    pub fn set_groove(&self, groove: Option<Groove>) -> anyhow::Result<()> {
+        self.tx.send(Msg::SetGroove(groove))?;
+        Ok(())
+    }
+
+    pub fn transport_status(&self) -> TransportStatus {
+        *self.transport_status.lock().unwrap()
+    }
+
+    pub fn toggle_recording(&self) -> anyhow::Result<()> {
+        self.tx.send(Msg::ToggleRecording)?;
+        Ok(())
+    }
+
+    pub fn panic(&self) -> anyhow::Result<()> {
+        self.tx.send(Msg::Panic)?;
+        Ok(())
+    }
+
     pub fn exit(&self) -> anyhow::Result<()> {
         self.tx.send(Msg::Exit)?;
         Ok(())
     }
 }
 
-impl<T: PlayerEventSource> Drop for PlayerActor<T> {
+impl<T: PlayerEventSource, C: Clock, M: MidiSink> Drop for PlayerActor<T, C, M> {
+    // also runs when the player thread panics, so nothing keeps sounding
+    // after a crash.
     fn drop(&mut self) {
-        debug!("Sending all notes off signal");
+        debug!("Silencing all notes");
 
-        self.midi_output_connection
-            .send(&AllNotesOff {}.to_midi_msg())
-            .expect("Could not send all notes out message");
+        if let Err(e) = self.panic() {
+            warn!("Could not silence all notes: {:?}", e);
+        }
     }
 }
 
 pub trait PlayerEventSource {
     fn next(&self) -> Option<Event>;
 }
+
+pub trait Clock {
+    /// Time elapsed since some fixed point in the past.
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        spin_sleep::sleep(duration)
+    }
+}
+
+pub trait MidiSink {
+    fn send(&mut self, msg: &[u8]) -> anyhow::Result<()>;
+}
+
+impl MidiSink for MidiOutputConnection {
+    fn send(&mut self, msg: &[u8]) -> anyhow::Result<()> {
+        MidiOutputConnection::send(self, msg).map_err(anyhow::Error::msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{
+        ChangeBpm, NoteOff, NoteOn, RampCurve, TempoRamp, Ticks, TimeSignature, Wait,
+    };
+    use std::{cell::Cell, cell::RefCell, collections::VecDeque, rc::Rc};
+
+    struct VecEventSource {
+        events: RefCell<VecDeque<Event>>,
+    }
+
+    impl PlayerEventSource for VecEventSource {
+        fn next(&self) -> Option<Event> {
+            self.events.borrow_mut().pop_front()
+        }
+    }
+
+    /// Virtual time that only advances when slept on. Every sleep overshoots
+    /// by `oversleep`, to simulate an imprecise OS scheduler.
+    struct VirtualClock {
+        now: Cell<Duration>,
+        oversleep: Duration,
+    }
+
+    impl Clock for Rc<VirtualClock> {
+        fn now(&self) -> Duration {
+            self.now.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.now.set(self.now.get() + duration + self.oversleep)
+        }
+    }
+
+    type CapturedMessages = Rc<RefCell<Vec<(Duration, Vec<u8>)>>>;
+
+    struct CapturingMidiSink {
+        clock: Rc<VirtualClock>,
+        messages: CapturedMessages,
+    }
+
+    impl MidiSink for CapturingMidiSink {
+        fn send(&mut self, msg: &[u8]) -> anyhow::Result<()> {
+            self.messages
+                .borrow_mut()
+                .push((self.clock.now(), msg.to_vec()));
+            Ok(())
+        }
+    }
+
+    type TestPlayer = PlayerActor<VecEventSource, Rc<VirtualClock>, CapturingMidiSink>;
+
+    fn test_player(oversleep: Duration) -> (TestPlayer, CapturedMessages) {
+        let clock = Rc::new(VirtualClock {
+            now: Cell::new(Duration::ZERO),
+            oversleep,
+        });
+        let messages = Rc::new(RefCell::new(vec![]));
+        let sink = CapturingMidiSink {
+            clock: clock.clone(),
+            messages: messages.clone(),
+        };
+        let source = VecEventSource {
+            events: RefCell::new(VecDeque::new()),
+        };
+        let (_, rx) = unbounded();
+
+        (
+            PlayerActor::new(source, clock, sink, rx, false, 0, Default::default()),
+            messages,
+        )
+    }
+
+    fn play(player: &mut TestPlayer, events: Vec<Event>) {
+        for event in events {
+            player.process_new_event(event).unwrap();
+        }
+    }
+
+    fn assert_close(actual: Duration, expected: Duration, tolerance: Duration) {
+        let difference = if actual > expected {
+            actual - expected
+        } else {
+            expected - actual
+        };
+
+        assert!(
+            difference <= tolerance,
+            "expected {:?} to be within {:?} of {:?}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    fn note_on(note: u8) -> Event {
+        Event::NoteOn(NoteOn { note })
+    }
+
+    fn note_off(note: u8) -> Event {
+        Event::NoteOff(NoteOff { note })
+    }
+
+    fn wait(ticks: Ticks) -> Event {
+        Event::Wait(Wait { ticks })
+    }
+
+    fn change_bpm(bpm: Bpm) -> Event {
+        Event::ChangeBpm(ChangeBpm { bpm })
+    }
+
+    #[test]
+    fn bpm_change_applies_to_following_waits() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+
+        play(
+            &mut player,
+            vec![
+                note_on(60),
+                wait(TICKS_PER_BEAT),
+                note_off(60),
+                change_bpm(60.0),
+                note_on(62),
+                wait(TICKS_PER_BEAT),
+                note_off(62),
+            ],
+        );
+
+        let times: Vec<Duration> = messages.borrow().iter().map(|(t, _)| *t).collect();
+        let expected = [0, 500, 500, 1500].map(Duration::from_millis);
+
+        assert_eq!(times, expected);
+    }
+
+    #[test]
+    fn tempo_ramp_speeds_up_the_following_waits() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+
+        play(
+            &mut player,
+            vec![
+                change_bpm(60.0),
+                Event::TempoRamp(TempoRamp {
+                    to_bpm: 120.0,
+                    over_ticks: TICKS_PER_BEAT,
+                    curve: RampCurve::Linear,
+                }),
+                note_on(60),
+                wait(TICKS_PER_BEAT / 2),
+                wait(TICKS_PER_BEAT),
+                note_off(60),
+            ],
+        );
+
+        // ln 2 seconds for the beat of the ramp, then half a beat at 120.
+        let (last_time, _) = *messages.borrow().last().unwrap();
+        assert_close(
+            last_time,
+            Duration::from_secs_f64(2f64.ln() + 0.25),
+            Duration::from_micros(1),
+        );
+        assert_eq!(player.tempo.bpm(), 120.0);
+    }
+
+    #[test]
+    fn stopping_mid_ramp_resumes_the_ramp() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+        player.player_status = PlayerStatus::Playing;
+
+        play(
+            &mut player,
+            vec![
+                change_bpm(60.0),
+                Event::TempoRamp(TempoRamp {
+                    to_bpm: 120.0,
+                    over_ticks: TICKS_PER_BEAT,
+                    curve: RampCurve::Linear,
+                }),
+                note_on(60),
+                wait(TICKS_PER_BEAT / 2),
+            ],
+        );
+        player.stop().unwrap();
+        assert_eq!(player.tempo.bpm(), 90.0);
+
+        player.player_status = PlayerStatus::Playing;
+        play(
+            &mut player,
+            vec![
+                wait(TICKS_PER_BEAT / 2),
+                wait(TICKS_PER_BEAT / 2),
+                note_on(62),
+            ],
+        );
+
+        // the same as without stopping, ln 2 seconds for the ramp and half a
+        // beat at 120.
+        let (last_time, _) = *messages.borrow().last().unwrap();
+        assert_close(
+            last_time,
+            Duration::from_secs_f64(2f64.ln() + 0.25),
+            Duration::from_micros(1),
+        );
+        assert_eq!(player.tempo.bpm(), 120.0);
+    }
+
+    #[test]
+    fn the_tick_playing_is_interpolated_up_to_the_next_events() {
+        let reached_at = Instant::now();
+        let status = TransportStatus {
+            playing: true,
+            bpm: 120.0,
+            tick: 100,
+            tick_reached_at: reached_at,
+            next_tick: 100 + u64::from(TICKS_PER_BEAT),
+            position: BarBeatTick::default(),
+        };
+
+        assert_eq!(status.tick_at(reached_at), 100);
+        assert_eq!(
+            status.tick_at(reached_at + Duration::from_millis(250)),
+            100 + u64::from(TICKS_PER_BEAT) / 2
+        );
+        assert_eq!(
+            status.tick_at(reached_at + Duration::from_secs(10)),
+            status.next_tick
+        );
+
+        let stopped = TransportStatus {
+            playing: false,
+            ..status
+        };
+        assert_eq!(stopped.tick_at(reached_at + Duration::from_secs(10)), 100);
+    }
+
+    #[test]
+    fn stopping_keeps_the_stream_position() {
+        let (mut player, _) = test_player(Duration::ZERO);
+        player.groove = Some(Groove::swing(75.0, 8).unwrap());
+        player.player_status = PlayerStatus::Playing;
+
+        play(
+            &mut player,
+            vec![
+                Event::TimeSignature(TimeSignature {
+                    numerator: 3,
+                    denominator: 4,
+                }),
+                wait(TICKS_PER_BEAT * 5 / 2),
+            ],
+        );
+        player.stop().unwrap();
+
+        // the events continue on the swung eighth, in the same bar.
+        assert_eq!(player.played_ticks, u64::from(TICKS_PER_BEAT) * 5 / 2);
+        assert_eq!(player.bars.time_signature().numerator, 3);
+        assert_eq!(
+            player.transport_status.lock().unwrap().tick,
+            player.played_ticks
+        );
+        assert!(player.dispatch_time() > Duration::ZERO);
+    }
+
+    #[test]
+    fn invalid_time_signatures_are_not_recorded() {
+        let (mut player, _) = test_player(Duration::ZERO);
+        player.recorder = Some(Recorder::new(0, 120.0));
+
+        play(
+            &mut player,
+            vec![Event::TimeSignature(TimeSignature {
+                numerator: 4,
+                denominator: 6,
+            })],
+        );
+
+        assert!(player.recorder.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn oversleeping_does_not_accumulate() {
+        let oversleep = Duration::from_millis(2);
+        let (mut player, messages) = test_player(oversleep);
+
+        let mut events = vec![];
+        for _ in 0..100 {
+            events.push(note_on(60));
+            events.push(wait(TICKS_PER_BEAT / 4));
+            events.push(note_off(60));
+        }
+        play(&mut player, events);
+
+        // every wait overshoots, but the next one is shortened to compensate,
+        // so the last note is late by a single oversleep only.
+        let (last_time, _) = *messages.borrow().last().unwrap();
+        assert_close(
+            last_time,
+            Duration::from_millis(100 * 125),
+            oversleep + Duration::from_millis(1),
+        );
+    }
+
+    #[test]
+    fn hours_of_waits_do_not_drift() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+        // a sixteenth note at 90.5 BPM is not a whole number of nanoseconds.
+        // 10 hours of them.
+        let sixteenths = 10 * 60 * 905 * 4 / 10;
+
+        play(&mut player, vec![change_bpm(90.5)]);
+        for _ in 0..sixteenths {
+            play(&mut player, vec![wait(TICKS_PER_BEAT / 4)]);
+        }
+        play(&mut player, vec![note_on(60)]);
+
+        let (last_time, _) = *messages.borrow().last().unwrap();
+        assert_eq!(last_time, Duration::from_secs(10 * 60 * 60));
+    }
+
+    #[test]
+    fn swing_delays_off_beat_notes() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+        player.groove = Some(Groove::swing(75.0, 8).unwrap());
+
+        play(
+            &mut player,
+            vec![
+                note_on(60),
+                wait(TICKS_PER_BEAT / 2),
+                note_on(62),
+                wait(TICKS_PER_BEAT / 2),
+                note_on(64),
+            ],
+        );
+
+        let times: Vec<Duration> = messages.borrow().iter().map(|(t, _)| *t).collect();
+        let expected = [0, 375, 500].map(Duration::from_millis);
+
+        assert_eq!(times, expected);
+    }
+
+    #[test]
+    fn stop_releases_sounding_notes() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+        player.player_status = PlayerStatus::Playing;
+
+        play(
+            &mut player,
+            vec![note_on(60), note_on(64), wait(TICKS_PER_BEAT), note_off(60)],
+        );
+        player.stop().unwrap();
+
+        let sent: Vec<Vec<u8>> = messages.borrow().iter().map(|(_, m)| m.clone()).collect();
+        assert_eq!(sent[3..], [vec![0x80, 64, 0]]);
+        assert!(player.sounding_notes.is_empty());
+    }
+
+    #[test]
+    fn panic_silences_every_channel() {
+        let (mut player, messages) = test_player(Duration::ZERO);
+
+        play(&mut player, vec![note_on(60)]);
+        player.panic().unwrap();
+
+        let sent: Vec<Vec<u8>> = messages.borrow().iter().map(|(_, m)| m.clone()).collect();
+        assert_eq!(sent[1], [0x80, 60, 0]);
+        for channel in 0..16 {
+            assert!(sent.contains(&vec![0xB0 | channel, 123, 0]));
+            assert!(sent.contains(&vec![0xB0 | channel, 120, 0]));
+        }
+    }
+}
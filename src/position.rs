@@ -0,0 +1,227 @@
+use crate::{
+    event::{Bpm, Event, TimeSignature, TICKS_PER_BEAT},
+    tempo::Tempo,
+};
+use log::warn;
+use serde::Serialize;
+use std::fmt;
+
+/// Converts absolute ticks into bars and beats, following time signature
+/// changes. A change always starts a new bar.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BarTracker {
+    time_signature: TimeSignature,
+    // where the current time signature took effect.
+    since_tick: u64,
+    since_bar: u64,
+}
+
+impl BarTracker {
+    pub fn time_signature(&self) -> TimeSignature {
+        self.time_signature
+    }
+
+    /// Returns whether `time_signature` was valid, and so applied.
+    pub fn set_time_signature(&mut self, tick: u64, time_signature: TimeSignature) -> bool {
+        if !time_signature.is_valid() {
+            warn!("Ignoring invalid time signature {:?}", time_signature);
+            return false;
+        }
+
+        let current = self.bar_beat_tick(tick);
+        let starts_bar = current.beat == 0 && current.tick == 0;
+
+        self.since_bar = if starts_bar {
+            current.bar
+        } else {
+            current.bar + 1
+        };
+        self.since_tick = tick;
+        self.time_signature = time_signature;
+        true
+    }
+
+    /// Length of a beat, which is a quarter note in 4/4 and an eighth note
+    /// in 6/8.
+    pub fn ticks_per_beat(&self) -> u64 {
+        u64::from(TICKS_PER_BEAT) * 4 / u64::from(self.time_signature.denominator)
+    }
+
+    pub fn ticks_per_bar(&self) -> u64 {
+        self.ticks_per_beat() * u64::from(self.time_signature.numerator)
+    }
+
+    /// Ticks before the time signature took effect count as bars of the
+    /// current one.
+    pub fn bar_beat_tick(&self, tick: u64) -> BarBeatTick {
+        let (bar, within_bar) = if tick >= self.since_tick {
+            let elapsed = tick - self.since_tick;
+            (
+                self.since_bar + elapsed / self.ticks_per_bar(),
+                elapsed % self.ticks_per_bar(),
+            )
+        } else {
+            (tick / self.ticks_per_bar(), tick % self.ticks_per_bar())
+        };
+
+        BarBeatTick {
+            bar,
+            beat: within_bar / self.ticks_per_beat(),
+            tick: within_bar % self.ticks_per_beat(),
+        }
+    }
+
+    /// First tick at or after `tick` that starts a beat, or a group of
+    /// `bars` bars when given.
+    pub fn next_boundary(&self, tick: u64, bars: Option<u64>) -> u64 {
+        let position = self.bar_beat_tick(tick);
+
+        match bars {
+            None if position.tick == 0 => tick,
+            None => tick + self.ticks_per_beat() - position.tick,
+            Some(bars) => {
+                let bars = bars.max(1);
+                let into_bar = position.beat * self.ticks_per_beat() + position.tick;
+                let into_group = (position.bar % bars) * self.ticks_per_bar() + into_bar;
+
+                if into_group == 0 {
+                    tick
+                } else {
+                    tick + bars * self.ticks_per_bar() - into_group
+                }
+            }
+        }
+    }
+}
+
+/// 0-based, shown 1-based like in a DAW, e.g. `17:1:0`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BarBeatTick {
+    pub bar: u64,
+    pub beat: u64,
+    pub tick: u64,
+}
+
+impl fmt::Display for BarBeatTick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.bar + 1, self.beat + 1, self.tick)
+    }
+}
+
+/// A point in the event stream, found by adding up the events before it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Position {
+    pub tick: u64,
+    pub tempo: Tempo,
+    pub bars: BarTracker,
+}
+
+impl Position {
+    /// Moves past `event`.
+    pub fn advance(&mut self, event: &Event) {
+        match event {
+            Event::Wait(wait) => {
+                self.tick += u64::from(wait.ticks);
+                self.tempo.advance(u64::from(wait.ticks));
+            }
+            Event::ChangeBpm(change_bpm) => self.tempo.set_bpm(change_bpm.bpm),
+            Event::TempoRamp(ramp) => self.tempo.start_ramp(ramp),
+            Event::TimeSignature(time_signature) => {
+                self.bars.set_time_signature(self.tick, *time_signature);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn transport_info(&self, playing: bool) -> TransportInfo {
+        let position = self.bars.bar_beat_tick(self.tick);
+        let time_signature = self.bars.time_signature();
+
+        TransportInfo {
+            tick: self.tick,
+            bar: position.bar,
+            beat: position.beat,
+            numerator: time_signature.numerator,
+            denominator: time_signature.denominator,
+            bpm: self.tempo.bpm(),
+            playing,
+            seconds: self.tempo.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+/// What scripts see as `murmel.transport`. Bars and beats are 0-based.
+#[derive(Serialize, Debug)]
+pub struct TransportInfo {
+    pub tick: u64,
+    pub bar: u64,
+    pub beat: u64,
+    pub numerator: u8,
+    pub denominator: u8,
+    pub bpm: Bpm,
+    pub playing: bool,
+    pub seconds: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEAT: u64 = TICKS_PER_BEAT as u64;
+
+    fn bbt(bar: u64, beat: u64, tick: u64) -> BarBeatTick {
+        BarBeatTick { bar, beat, tick }
+    }
+
+    #[test]
+    fn counts_bars_in_four_four() {
+        let bars = BarTracker::default();
+
+        assert_eq!(bars.bar_beat_tick(0), bbt(0, 0, 0));
+        assert_eq!(bars.bar_beat_tick(BEAT * 5 + 7), bbt(1, 1, 7));
+        assert_eq!(bars.bar_beat_tick(BEAT * 5 + 7).to_string(), "2:2:7");
+    }
+
+    #[test]
+    fn time_signature_change_mid_bar_starts_a_new_bar() {
+        let mut bars = BarTracker::default();
+        bars.set_time_signature(
+            BEAT * 6,
+            TimeSignature {
+                numerator: 6,
+                denominator: 8,
+            },
+        );
+
+        // bar 1 was cut short after 2 beats, 6/8 starts with bar 2.
+        assert_eq!(bars.bar_beat_tick(BEAT * 6), bbt(2, 0, 0));
+        assert_eq!(bars.bar_beat_tick(BEAT * 9 + BEAT / 2), bbt(3, 1, 0));
+    }
+
+    #[test]
+    fn invalid_time_signatures_are_ignored() {
+        let mut bars = BarTracker::default();
+
+        for (numerator, denominator) in [(0, 4), (3, 0), (7, 6), (4, 128)] {
+            let time_signature = TimeSignature {
+                numerator,
+                denominator,
+            };
+            assert!(!bars.set_time_signature(BEAT, time_signature));
+        }
+
+        assert_eq!(bars.time_signature(), TimeSignature::default());
+        assert_eq!(bars.bar_beat_tick(BEAT * 5), bbt(1, 1, 0));
+    }
+
+    #[test]
+    fn next_boundary_rounds_up_to_the_grid() {
+        let bars = BarTracker::default();
+
+        assert_eq!(bars.next_boundary(BEAT, None), BEAT);
+        assert_eq!(bars.next_boundary(BEAT + 1, None), BEAT * 2);
+        assert_eq!(bars.next_boundary(BEAT, Some(1)), BEAT * 4);
+        assert_eq!(bars.next_boundary(BEAT * 5, Some(2)), BEAT * 8);
+        assert_eq!(bars.next_boundary(BEAT * 8, Some(2)), BEAT * 8);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::event::{Bpm, TimeSignature};
+use anyhow::anyhow;
+use midly::{
+    live::LiveEvent,
+    num::{u15, u24, u28},
+    Arena, Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// resolution of the written MIDI files, independent of TICKS_PER_BEAT.
+const RECORDING_TICKS_PER_BEAT: u16 = 960;
+
+const MICROSECONDS_PER_MINUTE: f64 = 60_000_000.0;
+
+// the usual values for the time signature meta event.
+const MIDI_CLOCKS_PER_CLICK: u8 = 24;
+const THIRTY_SECONDS_PER_QUARTER: u8 = 8;
+
+// MIDI files keep the time signature and tempo in meta events, not messages.
+enum RecordedKind {
+    Midi(Vec<u8>),
+    TimeSignature(TimeSignature),
+    Tempo(Bpm),
+}
+
+struct RecordedMessage {
+    time: Duration,
+    kind: RecordedKind,
+}
+
+/// Captures MIDI messages sent by the player, and writes them as a Standard
+/// MIDI File.
+pub struct Recorder {
+    messages: Vec<RecordedMessage>,
+    random_seed: u64,
+    // the last recorded tempo, and the one before the first message.
+    bpm: Bpm,
+    start_bpm: Bpm,
+}
+
+impl Recorder {
+    pub fn new(random_seed: u64, bpm: Bpm) -> Self {
+        Recorder {
+            messages: vec![],
+            random_seed,
+            bpm,
+            start_bpm: bpm,
+        }
+    }
+
+    /// `time` is the time the message was scheduled for, measured from the
+    /// start of playback. Messages don't have to be recorded in order.
+    pub fn record(&mut self, time: Duration, msg: &[u8]) {
+        self.push(time, RecordedKind::Midi(msg.to_vec()));
+    }
+
+    pub fn record_time_signature(&mut self, time: Duration, time_signature: TimeSignature) {
+        self.push(time, RecordedKind::TimeSignature(time_signature));
+    }
+
+    /// The tempo from `time` on. Tempo ramps are recorded as a series of
+    /// these, each with the average tempo of its part of the ramp, so the
+    /// file plays back with the same timing.
+    pub fn record_tempo(&mut self, time: Duration, bpm: Bpm) {
+        if bpm != self.bpm {
+            self.bpm = bpm;
+            self.push(time, RecordedKind::Tempo(bpm));
+        }
+    }
+
+    fn push(&mut self, time: Duration, kind: RecordedKind) {
+        self.messages.push(RecordedMessage { time, kind });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Writes everything recorded so far to a new timestamped file in
+    /// `directory`, and clears the recorder. Never overwrites a file.
+    pub fn write_to_file(&mut self, directory: &Path) -> anyhow::Result<PathBuf> {
+        let (path, file) = create_recording_file(directory)?;
+
+        let arena = Arena::new();
+        let seed_text = format!("murmel random seed {}", self.random_seed);
+        let mut bpm = self.start_bpm;
+        let mut track = vec![
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::Text(seed_text.as_bytes())),
+            },
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::Tempo(bpm_to_tempo(bpm))),
+            },
+        ];
+        let mut previous_time = Duration::ZERO;
+        let mut pending_delta = 0.0;
+
+        // grooves can move messages past ones recorded after them.
+        self.messages.sort_by_key(|message| message.time);
+
+        for message in self.messages.iter() {
+            // the time since the previous message was played with the
+            // previous tempo, so that's what it needs to be converted with.
+            let elapsed = message.time.saturating_sub(previous_time);
+            pending_delta +=
+                elapsed.as_secs_f64() / 60.0 * bpm * f64::from(RECORDING_TICKS_PER_BEAT);
+            previous_time = message.time;
+
+            let kind = match &message.kind {
+                RecordedKind::Tempo(new_bpm) => {
+                    bpm = *new_bpm;
+                    TrackEventKind::Meta(MetaMessage::Tempo(bpm_to_tempo(bpm)))
+                }
+                RecordedKind::Midi(msg) => LiveEvent::parse(msg)
+                    .map_err(|e| anyhow!("Could not parse recorded MIDI message: {:?}", e))?
+                    .as_track_event(&arena),
+                RecordedKind::TimeSignature(time_signature) => {
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(
+                        time_signature.numerator,
+                        // stored as a power of two.
+                        time_signature.denominator.trailing_zeros() as u8,
+                        MIDI_CLOCKS_PER_CLICK,
+                        THIRTY_SECONDS_PER_QUARTER,
+                    ))
+                }
+            };
+
+            track.push(TrackEvent {
+                delta: take_delta(&mut pending_delta),
+                kind,
+            });
+        }
+
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(RECORDING_TICKS_PER_BEAT)),
+        ));
+        smf.tracks.push(track);
+        smf.write_std(BufWriter::new(file))?;
+
+        self.messages.clear();
+        self.start_bpm = self.bpm;
+
+        Ok(path)
+    }
+}
+
+// `murmel-<milliseconds>.mid`, with a counter if that is taken, e.g. by a take
+// stopped and started again within the same millisecond.
+fn create_recording_file(directory: &Path) -> anyhow::Result<(PathBuf, File)> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+    for attempt in 1.. {
+        let name = match attempt {
+            1 => format!("murmel-{}.mid", timestamp),
+            n => format!("murmel-{}-{}.mid", timestamp, n),
+        };
+        let path = directory.join(name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    unreachable!()
+}
+
+// rounds the accumulated delta to whole ticks, carrying the remainder over to
+// the next event so rounding errors don't accumulate.
+fn take_delta(pending_delta: &mut f64) -> u28 {
+    let delta = pending_delta.round();
+    *pending_delta -= delta;
+    u28::new(delta as u32)
+}
+
+fn bpm_to_tempo(bpm: Bpm) -> u24 {
+    // the slowest tempo a MIDI file can hold is about 3.6 BPM.
+    u24::new(((MICROSECONDS_PER_MINUTE / bpm).round() as u32).min(u24::max_value().as_int()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    #[test]
+    fn takes_in_quick_succession_get_their_own_files() {
+        let directory = std::env::temp_dir().join(format!("murmel-recorder-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let mut recorder = Recorder::new(0, 120.0);
+
+        recorder.record(Duration::ZERO, &[0x90, 60, 100]);
+        let first = recorder.write_to_file(&directory).unwrap();
+        recorder.record(Duration::ZERO, &[0x90, 62, 100]);
+        let second = recorder.write_to_file(&directory).unwrap();
+
+        assert_ne!(first, second);
+        assert!(Smf::parse(&fs::read(&first).unwrap()).is_ok());
+        assert!(Smf::parse(&fs::read(&second).unwrap()).is_ok());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
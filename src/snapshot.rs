@@ -0,0 +1,786 @@
+use crate::{
+    builtin_modules::BUILTIN_MODULES,
+    import_map::ImportMap,
+    ops::{murmel_extension, OpsContext},
+    transpile_cache::{content_hash, TranspileCache},
+    ts_module_loader::transpile,
+};
+use anyhow::{anyhow, bail};
+use deno_ast::{
+    swc::ast::{Decl, DefaultDecl, ExportSpecifier, ModuleDecl, ModuleExportName, ModuleItem, Pat},
+    MediaType, ParseParams, SourceRangedForSpanned, SourceTextInfo,
+};
+use deno_core::{serde_json, JsRuntime, ModuleSpecifier, RuntimeOptions};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::Arc,
+};
+
+pub const CREATE_SNAPSHOT_ARG: &str = "--create-snapshot";
+
+// bump when the snapshot contents change in a way the key doesn't cover.
+const SNAPSHOT_VERSION: &str = "1";
+
+// where the modules evaluated into the snapshot put their exports.
+const REGISTRY: &str = "globalThis.__murmelModules";
+
+// the local the default export of a module is bound to in its script.
+const DEFAULT_EXPORT: &str = "__murmel_default";
+
+/// A startup snapshot, and the modules that were evaluated into it.
+#[derive(Clone)]
+pub struct RuntimeSnapshot {
+    pub data: &'static [u8],
+    pub modules: Arc<SnapshotModules>,
+}
+
+/// The modules in a snapshot by specifier, with a hash of the source they
+/// were evaluated from.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SnapshotModules {
+    modules: HashMap<String, SnapshotModule>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SnapshotModule {
+    hash: String,
+    exports: Vec<String>,
+}
+
+impl SnapshotModules {
+    /// A module re-exporting what the snapshot holds for `specifier`, or
+    /// `None` if it isn't in the snapshot or `source` changed since.
+    pub fn facade(&self, specifier: &ModuleSpecifier, source: &str) -> Option<String> {
+        let module = self.modules.get(specifier.as_str())?;
+        if module.hash != content_hash(specifier, source) {
+            return None;
+        }
+
+        let mut code = format!(
+            "const module = {}[{}];\n",
+            REGISTRY,
+            serde_json::to_string(specifier.as_str()).ok()?
+        );
+        let mut names = vec![];
+        for (i, export) in module.exports.iter().enumerate() {
+            code.push_str(&format!(
+                "const export{} = module[{}];\n",
+                i,
+                serde_json::to_string(export).ok()?
+            ));
+            names.push(format!("export{} as {}", i, export));
+        }
+        code.push_str(&format!("export {{ {} }};\n", names.join(", ")));
+
+        Some(code)
+    }
+}
+
+// a module rewritten into a classic script, which V8 can snapshot.
+#[derive(Serialize, Deserialize)]
+struct SnapshotScript {
+    specifier: String,
+    hash: String,
+    code: String,
+    exports: Vec<String>,
+}
+
+/// Writes a V8 snapshot of a fresh runtime with the murmel runtime JS and
+/// the scripts in `scripts_path` evaluated, and next to it the modules that
+/// made it in. V8 can only be put in snapshot mode once per process, so this
+/// runs in a child process (see `load_snapshot`).
+///
+/// deno_core drops the module map when snapshotting, so modules are
+/// evaluated as classic scripts that register their exports, and served to
+/// scripts as facades over that registry.
+pub fn create_snapshot(path: &Path, scripts_path: &Path) -> anyhow::Result<()> {
+    let scripts: Vec<SnapshotScript> = serde_json::from_slice(&fs::read(scripts_path)?)?;
+
+    // the ops have to be registered in the same order as when the snapshot
+    // is loaded, their state is not part of the snapshot.
+    let mut js_runtime = JsRuntime::new(RuntimeOptions {
+        will_snapshot: true,
+        extensions: vec![murmel_extension(OpsContext {
+            params: Default::default(),
+            midi_input: Default::default(),
+            random_seed: 0,
+            transport_status: Default::default(),
+        })],
+        ..Default::default()
+    });
+
+    let mut modules = SnapshotModules::default();
+    for script in scripts {
+        // a module that fails here is loaded as usual instead.
+        if js_runtime
+            .execute_script(&script.specifier, &script.code)
+            .is_ok()
+        {
+            modules.modules.insert(
+                script.specifier,
+                SnapshotModule {
+                    hash: script.hash,
+                    exports: script.exports,
+                },
+            );
+        }
+    }
+
+    fs::write(manifest_path(path), serde_json::to_vec(&modules)?)?;
+    let snapshot = js_runtime.snapshot();
+    fs::write(path, &*snapshot)?;
+
+    Ok(())
+}
+
+/// The startup snapshot every event generator is created from, with
+/// murmel:std and the libraries in the import map of `entrypoint` already
+/// evaluated. Created in a child process the first time and cached on disk
+/// after, keyed by the murmel binary and the module sources. Returns `None`
+/// if the snapshot could not be created, generators are then bootstrapped
+/// as usual.
+pub fn load_snapshot(
+    entrypoint: &Path,
+    transpile_cache: &TranspileCache,
+) -> Option<RuntimeSnapshot> {
+    match load_or_create_snapshot(entrypoint, transpile_cache) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("Could not create runtime snapshot: {:?}", e);
+            None
+        }
+    }
+}
+
+fn load_or_create_snapshot(
+    entrypoint: &Path,
+    transpile_cache: &TranspileCache,
+) -> anyhow::Result<RuntimeSnapshot> {
+    let scripts = snapshot_scripts(entrypoint, transpile_cache);
+    let directory = snapshot_directory()?;
+    let key = SnapshotKey::new(&scripts)?;
+    let path = directory.join(format!("{}.bin", key));
+
+    let (data, modules) = match read_snapshot(&path) {
+        Some(snapshot) => {
+            info!("Using cached runtime snapshot {}", path.display());
+            snapshot
+        }
+        None => {
+            create_snapshot_in_child_process(&directory, &path, &scripts)?;
+            remove_stale_snapshots(&directory, &key);
+            let snapshot = read_snapshot(&path)
+                .ok_or_else(|| anyhow!("Could not read snapshot {}", path.display()))?;
+            info!(
+                "Created runtime snapshot ({} bytes) with {} of {} modules",
+                snapshot.0.len(),
+                snapshot.1.modules.len(),
+                scripts.len()
+            );
+            snapshot
+        }
+    };
+
+    Ok(RuntimeSnapshot {
+        // lives as long as the process, every generator uses it.
+        data: Box::leak(data.into_boxed_slice()),
+        modules: Arc::new(modules),
+    })
+}
+
+fn read_snapshot(path: &Path) -> Option<(Vec<u8>, SnapshotModules)> {
+    for path in [path, &manifest_path(path)] {
+        if !is_private_file(path) {
+            if path.exists() {
+                warn!("Ignoring snapshot {} not owned by you", path.display());
+            }
+            return None;
+        }
+    }
+
+    let modules = serde_json::from_slice(&fs::read(manifest_path(path)).ok()?).ok()?;
+    let data = fs::read(path).ok()?;
+    Some((data, modules))
+}
+
+fn create_snapshot_in_child_process(
+    directory: &Path,
+    path: &Path,
+    scripts: &[SnapshotScript],
+) -> anyhow::Result<()> {
+    // written under names of this process first, so a concurrent start
+    // never reads half a snapshot.
+    let temp_path = directory.join(format!("murmel-{}.bin", process::id()));
+    let scripts_path = directory.join(format!("murmel-{}-scripts.json", process::id()));
+    fs::write(&scripts_path, serde_json::to_vec(scripts)?)?;
+
+    let status = Command::new(env::current_exe()?)
+        .arg(CREATE_SNAPSHOT_ARG)
+        .arg(&temp_path)
+        .arg(&scripts_path)
+        .status();
+    let _ = fs::remove_file(&scripts_path);
+
+    let status = status?;
+    if !status.success() {
+        let _ = fs::remove_file(manifest_path(&temp_path));
+        let _ = fs::remove_file(&temp_path);
+        bail!("Snapshot process failed with {}", status);
+    }
+
+    fs::rename(manifest_path(&temp_path), manifest_path(path))?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+// only earlier builds of this binary, their snapshots can't be used by
+// anyone anymore. Snapshots of other binaries and projects are left alone,
+// and so are the files of snapshots still being created.
+fn remove_stale_snapshots(directory: &Path, key: &SnapshotKey) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_stale = path
+            .file_name()
+            .map_or(false, |name| key.is_stale(&name.to_string_lossy()));
+        if is_stale && matches!(path.extension(), Some(ext) if ext == "bin" || ext == "json") {
+            debug!("Removing stale snapshot {}", path.display());
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn manifest_path(path: &Path) -> PathBuf {
+    path.with_extension("json")
+}
+
+// per user and private, a snapshot is deserialized by V8 as is, so nobody
+// else may be able to put one there.
+fn snapshot_directory() -> anyhow::Result<PathBuf> {
+    let directory = dirs::cache_dir()
+        .ok_or_else(|| anyhow!("No cache directory"))?
+        .join("murmel")
+        .join("snapshots");
+    create_private_directory(&directory)?;
+    Ok(directory)
+}
+
+fn create_private_directory(directory: &Path) -> anyhow::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(directory)?;
+
+    let metadata = fs::symlink_metadata(directory)?;
+    if !metadata.is_dir() || !is_owned_by_user(&metadata) {
+        bail!("{} is not a directory owned by you", directory.display());
+    }
+    #[cfg(unix)]
+    if std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o077 != 0 {
+        bail!("{} is accessible by other users", directory.display());
+    }
+
+    Ok(())
+}
+
+fn is_private_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).map_or(false, |metadata| {
+        metadata.is_file() && is_owned_by_user(&metadata)
+    })
+}
+
+#[cfg(unix)]
+fn is_owned_by_user(metadata: &fs::Metadata) -> bool {
+    // safe, geteuid can't fail.
+    std::os::unix::fs::MetadataExt::uid(metadata) == unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_user(_: &fs::Metadata) -> bool {
+    true
+}
+
+/// Names a snapshot `<binary>-<build>-<modules>`. A snapshot only works
+/// with the V8 it was made by, so the binary is part of the key along with
+/// the modules.
+struct SnapshotKey {
+    binary: String,
+    build: String,
+    modules: String,
+}
+
+impl SnapshotKey {
+    fn new(scripts: &[SnapshotScript]) -> anyhow::Result<Self> {
+        let exe = env::current_exe()?;
+        let metadata = fs::metadata(&exe)?;
+
+        let binary = hash(&[exe.to_string_lossy().as_bytes()]);
+        let build = hash(&[
+            SNAPSHOT_VERSION.as_bytes(),
+            &metadata.len().to_le_bytes(),
+            format!("{:?}", metadata.modified()?).as_bytes(),
+        ]);
+        let scripts: Vec<&[u8]> = scripts
+            .iter()
+            .map(|script| script.hash.as_bytes())
+            .collect();
+
+        Ok(SnapshotKey {
+            binary,
+            build,
+            modules: hash(&scripts),
+        })
+    }
+
+    // made by the same binary before it was rebuilt.
+    fn is_stale(&self, file_name: &str) -> bool {
+        let mut parts = file_name.splitn(3, '-');
+        parts.next() == Some(&self.binary)
+            && parts.next().map_or(false, |build| build != self.build)
+    }
+}
+
+impl fmt::Display for SnapshotKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.binary, self.build, self.modules)
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+        hasher.update([0]);
+    }
+
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// murmel:std and the libraries mapped by name in the import map. Modules
+// that import others are left out, the module loader resolves those.
+fn snapshot_scripts(entrypoint: &Path, transpile_cache: &TranspileCache) -> Vec<SnapshotScript> {
+    let mut specifiers: Vec<ModuleSpecifier> = BUILTIN_MODULES
+        .iter()
+        .filter_map(|(name, _)| ModuleSpecifier::parse(name).ok())
+        .collect();
+
+    match ImportMap::for_entrypoint(entrypoint) {
+        Ok(Some(import_map)) => specifiers.extend(import_map.library_specifiers()),
+        Ok(None) => (),
+        Err(e) => debug!("Not snapshotting libraries: {:?}", e),
+    }
+
+    specifiers
+        .iter()
+        .filter_map(|specifier| {
+            let script = snapshot_script(specifier, transpile_cache);
+            if let Err(e) = &script {
+                debug!("Not snapshotting {}: {:?}", specifier, e);
+            }
+            script.ok()
+        })
+        .collect()
+}
+
+fn snapshot_script(
+    specifier: &ModuleSpecifier,
+    transpile_cache: &TranspileCache,
+) -> anyhow::Result<SnapshotScript> {
+    let (source, media_type) = match BUILTIN_MODULES
+        .iter()
+        .find(|(name, _)| *name == specifier.as_str())
+    {
+        Some((_, source)) => (source.to_string(), MediaType::TypeScript),
+        None => {
+            let path = specifier
+                .to_file_path()
+                .map_err(|()| anyhow!("not a local file"))?;
+            (fs::read_to_string(&path)?, MediaType::from(&path))
+        }
+    };
+
+    let code = match media_type {
+        MediaType::JavaScript | MediaType::Mjs => source.clone(),
+        MediaType::TypeScript | MediaType::Mts => {
+            transpile(transpile_cache, specifier, &source, media_type)?.code
+        }
+        _ => bail!("{} modules are not snapshotted", media_type),
+    };
+
+    let (code, exports) = classic_script(specifier.as_str(), &code)?;
+
+    Ok(SnapshotScript {
+        specifier: specifier.to_string(),
+        hash: content_hash(specifier, &source),
+        code,
+        exports,
+    })
+}
+
+// rewrites a JavaScript module without imports into a script that puts its
+// exports into the registry, returning the script and the export names.
+fn classic_script(specifier: &str, code: &str) -> anyhow::Result<(String, Vec<String>)> {
+    // would resolve differently, or not at all, from a script.
+    if code.contains("import(") || code.contains("import.meta") {
+        bail!("uses import() or import.meta");
+    }
+
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: specifier.to_string(),
+        text_info: SourceTextInfo::from_string(code.to_string()),
+        media_type: MediaType::JavaScript,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })?;
+    let start = parsed.text_info().range().start;
+    let byte_range = |range: deno_ast::SourceRange| range.as_byte_range(start);
+
+    // (exported name, local name), and the replacements of the export syntax.
+    let mut exports: Vec<(String, String)> = vec![];
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+
+    for item in parsed.module().body.iter() {
+        let decl = match item {
+            ModuleItem::ModuleDecl(decl) => decl,
+            ModuleItem::Stmt(_) => continue,
+        };
+
+        match decl {
+            // `export const a = 1`, `export function f() {}`
+            ModuleDecl::ExportDecl(export) => {
+                match &export.decl {
+                    Decl::Var(var) => {
+                        for declarator in &var.decls {
+                            match &declarator.name {
+                                Pat::Ident(name) => {
+                                    exports.push((name.id.sym.to_string(), name.id.sym.to_string()))
+                                }
+                                _ => bail!("exports a destructuring declaration"),
+                            }
+                        }
+                    }
+                    Decl::Fn(f) => exports.push((f.ident.sym.to_string(), f.ident.sym.to_string())),
+                    Decl::Class(c) => {
+                        exports.push((c.ident.sym.to_string(), c.ident.sym.to_string()))
+                    }
+                    _ => bail!("exports a TypeScript declaration"),
+                }
+
+                edits.push((
+                    byte_range(export.range()).start..byte_range(export.decl.range()).start,
+                    String::new(),
+                ));
+            }
+
+            // `export { a, b as c }`
+            ModuleDecl::ExportNamed(export) if export.src.is_none() => {
+                for specifier in &export.specifiers {
+                    let named = match specifier {
+                        ExportSpecifier::Named(named) => named,
+                        _ => bail!("has an unsupported export"),
+                    };
+                    let name = |name: &ModuleExportName| match name {
+                        ModuleExportName::Ident(ident) => Ok(ident.sym.to_string()),
+                        ModuleExportName::Str(_) => Err(anyhow!("has a string export name")),
+                    };
+                    let local = name(&named.orig)?;
+                    let exported = match &named.exported {
+                        Some(exported) => name(exported)?,
+                        None => local.clone(),
+                    };
+                    exports.push((exported, local));
+                }
+
+                edits.push((byte_range(export.range()), String::new()));
+            }
+
+            // `export default function f() {}`, `export default class {}`
+            ModuleDecl::ExportDefaultDecl(export) => {
+                let ident = match &export.decl {
+                    DefaultDecl::Fn(f) => f.ident.as_ref(),
+                    DefaultDecl::Class(c) => c.ident.as_ref(),
+                    DefaultDecl::TsInterfaceDecl(_) => bail!("exports a TypeScript declaration"),
+                };
+                let decl_range = byte_range(export.decl.range());
+
+                match ident {
+                    Some(ident) => {
+                        exports.push(("default".to_string(), ident.sym.to_string()));
+                        edits.push((
+                            byte_range(export.range()).start..decl_range.start,
+                            String::new(),
+                        ));
+                    }
+                    None => {
+                        exports.push(("default".to_string(), DEFAULT_EXPORT.to_string()));
+                        edits.push((
+                            byte_range(export.range()).start..decl_range.start,
+                            format!("const {} = ", DEFAULT_EXPORT),
+                        ));
+                        edits.push((decl_range.end..decl_range.end, ";".to_string()));
+                    }
+                }
+            }
+
+            // `export default expression`
+            ModuleDecl::ExportDefaultExpr(export) => {
+                exports.push(("default".to_string(), DEFAULT_EXPORT.to_string()));
+                edits.push((
+                    byte_range(export.range()).start..byte_range(export.expr.range()).start,
+                    format!("const {} = ", DEFAULT_EXPORT),
+                ));
+            }
+
+            _ => bail!("imports or re-exports other modules"),
+        }
+    }
+
+    let mut body = code.to_string();
+    edits.sort_by_key(|(range, _)| range.start);
+    for (range, replacement) in edits.into_iter().rev() {
+        body.replace_range(range, &replacement);
+    }
+
+    let mut registered = vec![];
+    for (exported, local) in &exports {
+        registered.push(format!("{}: {}", serde_json::to_string(exported)?, local));
+    }
+
+    // strict and with `this` undefined, like a module. Starts on the first
+    // line so line numbers in stack traces stay the same.
+    let script = format!(
+        "(function () {{ 'use strict'; {}\n;({} ??= {{}})[{}] = {{ {} }};\n}})();\n",
+        body,
+        REGISTRY,
+        serde_json::to_string(specifier)?,
+        registered.join(", ")
+    );
+
+    Ok((
+        script,
+        exports.into_iter().map(|(exported, _)| exported).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_is_script(code: &str) {
+        deno_ast::parse_script(ParseParams {
+            specifier: "file:///script.js".to_string(),
+            text_info: SourceTextInfo::from_string(code.to_string()),
+            media_type: MediaType::JavaScript,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })
+        .unwrap();
+    }
+
+    fn facade(exports: &[&str]) -> String {
+        let specifier = ModuleSpecifier::parse("file:///lib.js").unwrap();
+        let mut modules = SnapshotModules::default();
+        modules.modules.insert(
+            specifier.to_string(),
+            SnapshotModule {
+                hash: content_hash(&specifier, "source"),
+                exports: exports.iter().map(|name| name.to_string()).collect(),
+            },
+        );
+
+        assert!(modules.facade(&specifier, "edited source").is_none());
+        modules.facade(&specifier, "source").unwrap()
+    }
+
+    #[test]
+    fn exports_become_registry_entries() {
+        let (script, exports) = classic_script(
+            "file:///lib.js",
+            "export const a = 1, b = 2\nexport function f() {}\nconst c = 3\nexport { c as d, c }\nexport default class {}",
+        )
+        .unwrap();
+
+        assert_eq!(exports, vec!["a", "b", "f", "d", "c", "default"]);
+        assert!(
+            script.starts_with("(function () { 'use strict'; const a = 1, b = 2\nfunction f() {}")
+        );
+        assert!(script.contains("const __murmel_default = class {};"));
+        assert!(script.contains(
+            "[\"file:///lib.js\"] = { \"a\": a, \"b\": b, \"f\": f, \"d\": c, \"c\": c, \"default\": __murmel_default }"
+        ));
+        assert!(!script.contains("export"));
+        assert_is_script(&script);
+    }
+
+    #[test]
+    fn named_default_exports_keep_their_name() {
+        let (script, exports) =
+            classic_script("file:///lib.js", "export default function* song() {}").unwrap();
+
+        assert_eq!(exports, vec!["default"]);
+        assert!(script.contains("'use strict'; function* song() {}"));
+        assert!(script.contains("{ \"default\": song }"));
+    }
+
+    #[test]
+    fn modules_with_imports_are_not_snapshotted() {
+        for code in [
+            "import { a } from './a.js'\nexport const b = a",
+            "export { a } from './a.js'",
+            "export * from './a.js'",
+            "export const url = import.meta.url",
+            "export const load = () => import('./a.js')",
+        ] {
+            assert!(classic_script("file:///lib.js", code).is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn std_is_snapshotted() {
+        let specifier = ModuleSpecifier::parse("murmel:std").unwrap();
+        let script = snapshot_script(&specifier, &TranspileCache::default()).unwrap();
+
+        assert_eq!(script.exports, vec!["TICKS_PER_BEAT"]);
+    }
+
+    #[test]
+    fn sample_libraries_are_snapshotted() {
+        let scripts = snapshot_scripts(
+            &fs::canonicalize("sample_scripts/main.ts").unwrap(),
+            &TranspileCache::default(),
+        );
+        let specifiers: Vec<&str> = scripts.iter().map(|s| s.specifier.as_str()).collect();
+
+        assert_eq!(specifiers.len(), 2);
+        assert_eq!(specifiers[0], "murmel:std");
+        assert!(specifiers[1].ends_with("/sample_scripts/tonal/tonal.js"));
+        assert!(scripts[1].exports.contains(&"Note".to_string()));
+        for script in scripts {
+            assert_is_script(&script.code);
+        }
+    }
+
+    #[test]
+    fn facades_reexport_the_registry_entries() {
+        assert_eq!(
+            facade(&["Note", "default"]),
+            "const module = globalThis.__murmelModules[\"file:///lib.js\"];\n\
+             const export0 = module[\"Note\"];\n\
+             const export1 = module[\"default\"];\n\
+             export { export0 as Note, export1 as default };\n"
+        );
+
+        deno_ast::parse_module(ParseParams {
+            specifier: "file:///lib.js".to_string(),
+            text_info: SourceTextInfo::from_string(facade(&["default", "delete"])),
+            media_type: MediaType::JavaScript,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })
+        .unwrap();
+    }
+
+    fn test_key(build: &str) -> SnapshotKey {
+        SnapshotKey {
+            binary: "b1".to_string(),
+            build: build.to_string(),
+            modules: "m1".to_string(),
+        }
+    }
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("murmel-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn only_earlier_builds_of_this_binary_are_removed() {
+        let directory = test_directory("stale-snapshots");
+        fs::create_dir_all(&directory).unwrap();
+        let files = [
+            "b1-old-m1.bin",
+            "b1-old-m1.json",
+            "b1-new-m1.bin",
+            "b1-new-m2.bin",
+            "b2-old-m1.bin",
+            "murmel-123.bin",
+            "murmel-123-scripts.json",
+        ];
+        for file in files {
+            fs::write(directory.join(file), "").unwrap();
+        }
+
+        remove_stale_snapshots(&directory, &test_key("new"));
+
+        let mut left: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            left,
+            [
+                "b1-new-m1.bin",
+                "b1-new-m2.bin",
+                "b2-old-m1.bin",
+                "murmel-123-scripts.json",
+                "murmel-123.bin"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshot_directories_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = test_directory("private-snapshots");
+        create_private_directory(&directory.join("snapshots")).unwrap();
+        let mode = fs::metadata(directory.join("snapshots"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        fs::set_permissions(&directory, fs::Permissions::from_mode(0o777)).unwrap();
+        let result = create_private_directory(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn snapshots_must_be_regular_files() {
+        let directory = test_directory("snapshot-files");
+        fs::create_dir_all(directory.join("dir.bin")).unwrap();
+        fs::write(directory.join("file.bin"), "").unwrap();
+
+        let file_is_private = is_private_file(&directory.join("file.bin"));
+        let dir_is_private = is_private_file(&directory.join("dir.bin"));
+        let missing_is_private = is_private_file(&directory.join("missing.bin"));
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(file_is_private);
+        assert!(!dir_is_private);
+        assert!(!missing_is_private);
+    }
+}
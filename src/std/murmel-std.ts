@@ -0,0 +1,45 @@
+// Served to scripts as `murmel:std`. The `Event` type is checked against
+// the Rust `event::Event` by the tests in `builtin_modules.rs`. Editor
+// declarations for it and the runtime globals come from `murmel types`.
+
+export type Event =
+    | {
+          type: 'NoteOn'
+          note: number
+      }
+    | {
+          type: 'Wait'
+          ticks: number
+      }
+    | {
+          type: 'NoteOff'
+          note: number
+      }
+    | {
+          type: 'Marker'
+      }
+    | {
+          type: 'ChangeBpm'
+          bpm: number
+      }
+    | {
+          type: 'TempoRamp'
+          toBpm: number
+          overTicks: number
+          curve: 'linear' | 'exponential'
+      }
+    | {
+          type: 'AllNotesOff'
+      }
+    | {
+          type: 'TimeSignature'
+          numerator: number
+          // a power of two, e.g. 8 for 6/8
+          denominator: number
+      }
+    | {
+          type: 'Print'
+          value: string
+      }
+
+export const TICKS_PER_BEAT = 55440
@@ -0,0 +1,373 @@
+use crate::event::{Bpm, RampCurve, TempoRamp, MAX_BPM, MIN_BPM, TICKS_PER_BEAT};
+use std::time::Duration;
+
+const SECONDS_PER_MINUTE: f64 = 60.0;
+const NANOS_PER_MINUTE: u128 = 60_000_000_000;
+
+// constant tempos are exact to a millionth of a BPM.
+const MICROS_PER_BPM: f64 = 1_000_000.0;
+
+/// Duration of `ticks` at a constant `bpm`, rounded to the nanosecond. Exact
+/// integer math, so the duration of many ticks is not the sum of rounding
+/// errors.
+fn constant_tempo_duration(ticks: u64, bpm: Bpm) -> Duration {
+    let micro_bpm = ((bpm * MICROS_PER_BPM).round() as u128).max(1);
+    let numerator = u128::from(ticks) * NANOS_PER_MINUTE * MICROS_PER_BPM as u128;
+    let denominator = micro_bpm * u128::from(TICKS_PER_BEAT);
+
+    Duration::from_nanos(((numerator + denominator / 2) / denominator) as u64)
+}
+
+// unchecked events can carry any number, a tempo of zero or below would
+// never get anywhere.
+fn clamp_bpm(bpm: Bpm) -> Bpm {
+    if bpm.is_nan() {
+        MIN_BPM
+    } else {
+        bpm.clamp(MIN_BPM, MAX_BPM)
+    }
+}
+
+/// The tempo along the event stream, either constant or following a ramp,
+/// and the time it took to get there.
+///
+/// Time is counted from the last tempo change, the anchor, rather than
+/// added up wait by wait. Rounding happens once per lookup and once per
+/// tempo change, so it doesn't accumulate over long pieces.
+#[derive(Clone, Copy, Debug)]
+pub struct Tempo {
+    // the constant tempo, or where the ramp started.
+    bpm: Bpm,
+    ramp: Option<Ramp>,
+    anchor: Duration,
+    ticks_since_anchor: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Ramp {
+    from_bpm: Bpm,
+    to_bpm: Bpm,
+    over_ticks: u64,
+    curve: RampCurve,
+}
+
+impl Ramp {
+    fn bpm_at(&self, tick: u64) -> Bpm {
+        let progress = tick as f64 / self.over_ticks as f64;
+
+        match self.curve {
+            RampCurve::Linear => self.from_bpm + (self.to_bpm - self.from_bpm) * progress,
+            RampCurve::Exponential => self.from_bpm * (self.to_bpm / self.from_bpm).powf(progress),
+        }
+    }
+
+    // the integral of the time per tick from the start of the ramp.
+    fn duration_until(&self, tick: u64) -> Duration {
+        if self.from_bpm == self.to_bpm {
+            return constant_tempo_duration(tick, self.from_bpm);
+        }
+
+        let ticks_per_beat = f64::from(TICKS_PER_BEAT);
+        let bpm = self.bpm_at(tick);
+
+        let seconds = match self.curve {
+            // bpm(t) = from + slope * t
+            RampCurve::Linear => {
+                let slope = (self.to_bpm - self.from_bpm) / self.over_ticks as f64;
+                SECONDS_PER_MINUTE / (ticks_per_beat * slope) * (bpm / self.from_bpm).ln()
+            }
+            // bpm(t) = from * e^(rate * t)
+            RampCurve::Exponential => {
+                let rate = (self.to_bpm / self.from_bpm).ln() / self.over_ticks as f64;
+                SECONDS_PER_MINUTE / (ticks_per_beat * rate) * (1.0 / self.from_bpm - 1.0 / bpm)
+            }
+        };
+
+        Duration::from_secs_f64(seconds)
+    }
+}
+
+impl Tempo {
+    /// Tempos outside `MIN_BPM..=MAX_BPM` are clamped, here and on changes.
+    pub fn new(bpm: Bpm) -> Self {
+        Tempo {
+            bpm: clamp_bpm(bpm),
+            ramp: None,
+            anchor: Duration::ZERO,
+            ticks_since_anchor: 0,
+        }
+    }
+
+    /// The BPM right now, part way through a ramp if there is one.
+    pub fn bpm(&self) -> Bpm {
+        match &self.ramp {
+            Some(ramp) => ramp.bpm_at(self.ticks_since_anchor),
+            None => self.bpm,
+        }
+    }
+
+    /// Time since the start, at the tempo changes along the way.
+    pub fn elapsed(&self) -> Duration {
+        self.anchor
+            + match &self.ramp {
+                Some(ramp) => ramp.duration_until(self.ticks_since_anchor),
+                None => constant_tempo_duration(self.ticks_since_anchor, self.bpm),
+            }
+    }
+
+    /// Jumps to `bpm`, ending any ramp.
+    pub fn set_bpm(&mut self, bpm: Bpm) {
+        let bpm = clamp_bpm(bpm);
+        if self.ramp.is_none() && bpm == self.bpm {
+            return;
+        }
+
+        self.move_anchor();
+        self.bpm = bpm;
+        self.ramp = None;
+    }
+
+    pub fn start_ramp(&mut self, ramp: &TempoRamp) {
+        if ramp.over_ticks == 0 {
+            self.set_bpm(ramp.to_bpm);
+            return;
+        }
+
+        let from_bpm = self.bpm();
+        self.move_anchor();
+        self.bpm = from_bpm;
+        self.ramp = Some(Ramp {
+            from_bpm,
+            to_bpm: clamp_bpm(ramp.to_bpm),
+            over_ticks: u64::from(ramp.over_ticks),
+            curve: ramp.curve,
+        });
+    }
+
+    /// Counts time from zero again, keeping the tempo and the rest of a
+    /// ramp.
+    pub fn restart(&mut self) {
+        let bpm = self.bpm();
+        self.ramp = self.ramp.map(|ramp| Ramp {
+            from_bpm: bpm,
+            over_ticks: ramp.over_ticks - self.ticks_since_anchor,
+            ..ramp
+        });
+        self.bpm = bpm;
+        self.anchor = Duration::ZERO;
+        self.ticks_since_anchor = 0;
+    }
+
+    /// Ticks until the current ramp ends, `None` without one.
+    pub fn ramp_ticks_left(&self) -> Option<u64> {
+        self.ramp
+            .map(|ramp| ramp.over_ticks - self.ticks_since_anchor)
+    }
+
+    /// How long the next `ticks` take.
+    pub fn duration_of(&self, ticks: u64) -> Duration {
+        let mut ahead = *self;
+        ahead.advance(ticks);
+        ahead.elapsed().saturating_sub(self.elapsed())
+    }
+
+    /// Moves `ticks` ahead. A ramp ending on the way is followed by its
+    /// final tempo.
+    pub fn advance(&mut self, ticks: u64) {
+        match (self.ramp, self.ramp_ticks_left()) {
+            (Some(ramp), Some(left)) if ticks >= left => {
+                self.ticks_since_anchor += left;
+                self.set_bpm(ramp.to_bpm);
+                self.ticks_since_anchor += ticks - left;
+            }
+            _ => self.ticks_since_anchor += ticks,
+        }
+    }
+
+    fn move_anchor(&mut self) {
+        self.anchor = self.elapsed();
+        self.ticks_since_anchor = 0;
+    }
+}
+
+impl Default for Tempo {
+    fn default() -> Self {
+        Tempo::new(120.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEAT: u64 = TICKS_PER_BEAT as u64;
+
+    fn assert_close(actual: Duration, expected_seconds: f64) {
+        assert!(
+            (actual.as_secs_f64() - expected_seconds).abs() < 1e-6,
+            "expected {:?} to be {}s",
+            actual,
+            expected_seconds
+        );
+    }
+
+    fn ramp(to_bpm: Bpm, beats: u32, curve: RampCurve) -> TempoRamp {
+        TempoRamp {
+            to_bpm,
+            over_ticks: TICKS_PER_BEAT * beats,
+            curve,
+        }
+    }
+
+    fn duration_of(tempo: &mut Tempo, ticks: u64) -> Duration {
+        let start = tempo.elapsed();
+        tempo.advance(ticks);
+        tempo.elapsed() - start
+    }
+
+    #[test]
+    fn constant_tempo_follows_bpm() {
+        assert_eq!(
+            duration_of(&mut Tempo::new(120.0), BEAT),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            duration_of(&mut Tempo::new(120.0), BEAT / 2),
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            duration_of(&mut Tempo::new(60.0), BEAT),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            duration_of(&mut Tempo::new(240.0), BEAT * 4),
+            Duration::from_secs(1)
+        );
+        assert_close(duration_of(&mut Tempo::new(90.5), BEAT), 60.0 / 90.5);
+    }
+
+    #[test]
+    fn many_small_waits_do_not_drift() {
+        // 7 ticks at 90.5 BPM are not a whole number of nanoseconds, rounding
+        // every wait would be off by up to 50µs here.
+        let mut tempo = Tempo::new(90.5);
+        let mut whole = Tempo::new(90.5);
+        let mut ticks = 0;
+
+        for _ in 0..100_000 {
+            tempo.advance(7);
+            ticks += 7;
+        }
+        whole.advance(ticks);
+
+        assert_eq!(tempo.elapsed(), whole.elapsed());
+    }
+
+    #[test]
+    fn tempo_changes_on_the_nanosecond_grid_do_not_drift() {
+        let mut tempo = Tempo::new(120.0);
+
+        // a bar at 120 BPM is 2s, at 150 BPM 1.6s.
+        for _ in 0..10_000 {
+            tempo.advance(BEAT * 4);
+            tempo.set_bpm(150.0);
+            tempo.advance(BEAT * 4);
+            tempo.set_bpm(120.0);
+        }
+
+        assert_eq!(tempo.elapsed(), Duration::from_secs(36_000));
+    }
+
+    #[test]
+    fn linear_ramp_integrates_the_tempo() {
+        let mut tempo = Tempo::new(60.0);
+        tempo.start_ramp(&ramp(120.0, 1, RampCurve::Linear));
+
+        // the integral of 1 / (1 + t) seconds per beat over one beat.
+        assert_close(duration_of(&mut tempo, BEAT), 2f64.ln());
+        assert_eq!(tempo.bpm(), 120.0);
+        assert_eq!(tempo.ramp_ticks_left(), None);
+    }
+
+    #[test]
+    fn exponential_ramp_integrates_the_tempo() {
+        let mut tempo = Tempo::new(60.0);
+        tempo.start_ramp(&ramp(120.0, 1, RampCurve::Exponential));
+
+        // the integral of 2^-t seconds per beat over one beat.
+        assert_close(duration_of(&mut tempo, BEAT), 0.5 / 2f64.ln());
+    }
+
+    #[test]
+    fn ramp_in_parts_takes_as_long_as_at_once() {
+        let mut whole = Tempo::new(100.0);
+        whole.start_ramp(&ramp(150.0, 8, RampCurve::Exponential));
+        whole.advance(BEAT * 10);
+
+        let mut parts = Tempo::new(100.0);
+        parts.start_ramp(&ramp(150.0, 8, RampCurve::Exponential));
+        for _ in 0..40 {
+            parts.advance(BEAT / 4);
+        }
+
+        assert_eq!(parts.elapsed(), whole.elapsed());
+
+        // the last two beats are at the final tempo.
+        let mut ramp_only = Tempo::new(100.0);
+        ramp_only.start_ramp(&ramp(150.0, 8, RampCurve::Exponential));
+        ramp_only.advance(BEAT * 8);
+        assert_eq!(
+            whole.elapsed() - ramp_only.elapsed(),
+            Duration::from_millis(800)
+        );
+    }
+
+    #[test]
+    fn restarting_keeps_the_rest_of_the_ramp() {
+        for curve in [RampCurve::Linear, RampCurve::Exponential] {
+            let mut whole = Tempo::new(60.0);
+            whole.start_ramp(&ramp(120.0, 4, curve));
+            whole.advance(BEAT);
+            let before = whole.elapsed();
+            whole.advance(BEAT * 4);
+
+            let mut restarted = Tempo::new(60.0);
+            restarted.start_ramp(&ramp(120.0, 4, curve));
+            restarted.advance(BEAT);
+            restarted.restart();
+            assert_eq!(restarted.elapsed(), Duration::ZERO);
+            assert_eq!(restarted.ramp_ticks_left(), Some(BEAT * 3));
+            restarted.advance(BEAT * 4);
+
+            assert_close(
+                restarted.elapsed(),
+                (whole.elapsed() - before).as_secs_f64(),
+            );
+            assert_eq!(restarted.bpm(), 120.0);
+        }
+    }
+
+    #[test]
+    fn tempos_out_of_range_are_clamped() {
+        let mut tempo = Tempo::new(120.0);
+        tempo.start_ramp(&ramp(0.0, 1, RampCurve::Linear));
+        // slowing down from 120 to 1 BPM over a beat.
+        assert_close(duration_of(&mut tempo, BEAT), 60.0 / 119.0 * 120f64.ln());
+        assert_eq!(tempo.bpm(), MIN_BPM);
+
+        let mut tempo = Tempo::new(120.0);
+        tempo.start_ramp(&ramp(-60.0, 1, RampCurve::Exponential));
+        tempo.advance(BEAT / 2);
+        assert!(tempo.elapsed() > Duration::ZERO);
+
+        tempo.set_bpm(-120.0);
+        assert_eq!(tempo.bpm(), MIN_BPM);
+        tempo.set_bpm(f64::NAN);
+        assert_eq!(tempo.bpm(), MIN_BPM);
+        assert_eq!(Tempo::new(1e9).bpm(), MAX_BPM);
+        assert_eq!(
+            duration_of(&mut Tempo::new(0.0), BEAT),
+            Duration::from_secs(60)
+        );
+    }
+}
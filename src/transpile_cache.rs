@@ -0,0 +1,198 @@
+use deno_core::{serde_json, ModuleSpecifier};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+// bump when the transpile options change, so stale disk entries are ignored.
+const CACHE_VERSION: &str = "1";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TranspiledModule {
+    pub code: String,
+    pub source_map: Option<String>,
+}
+
+/// Transpiled modules keyed by a hash of their source, shared by all event
+/// generators so a reload only transpiles the files that changed. Optionally
+/// also stored on disk, to survive restarts.
+#[derive(Clone, Default)]
+pub struct TranspileCache {
+    // by specifier, so an edited module replaces its previous version.
+    memory: Arc<Mutex<HashMap<String, (String, TranspiledModule)>>>,
+    directory: Option<PathBuf>,
+}
+
+impl TranspileCache {
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        TranspileCache {
+            memory: Default::default(),
+            directory,
+        }
+    }
+
+    pub fn get_or_transpile(
+        &self,
+        specifier: &ModuleSpecifier,
+        source: &str,
+        transpile: impl FnOnce() -> anyhow::Result<TranspiledModule>,
+    ) -> anyhow::Result<TranspiledModule> {
+        let hash = content_hash(specifier, source);
+
+        if let Some((cached_hash, module)) = self.memory.lock().unwrap().get(specifier.as_str()) {
+            if *cached_hash == hash {
+                debug!("Transpile cache hit for {}", specifier);
+                return Ok(module.clone());
+            }
+        }
+
+        let module = match self.read_from_disk(&hash) {
+            Some(module) => {
+                debug!("Transpile cache hit on disk for {}", specifier);
+                module
+            }
+            None => {
+                let module = transpile()?;
+                self.write_to_disk(&hash, &module);
+                module
+            }
+        };
+
+        self.memory
+            .lock()
+            .unwrap()
+            .insert(specifier.to_string(), (hash, module.clone()));
+
+        Ok(module)
+    }
+
+    fn read_from_disk(&self, hash: &str) -> Option<TranspiledModule> {
+        let path = self.directory.as_ref()?.join(format!("{}.json", hash));
+        let contents = fs::read(path).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    fn write_to_disk(&self, hash: &str, module: &TranspiledModule) {
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => return,
+        };
+
+        if let Err(e) = write_entry(directory, hash, module) {
+            warn!("Could not write transpile cache: {:?}", e);
+        }
+    }
+}
+
+fn write_entry(directory: &Path, hash: &str, module: &TranspiledModule) -> anyhow::Result<()> {
+    fs::create_dir_all(directory)?;
+    fs::write(
+        directory.join(format!("{}.json", hash)),
+        serde_json::to_vec(module)?,
+    )?;
+    Ok(())
+}
+
+/// Hash of a module source. The specifier is part of it because it ends up
+/// in the source map.
+pub fn content_hash(specifier: &ModuleSpecifier, source: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(CACHE_VERSION);
+    hasher.update([0]);
+    hasher.update(specifier.as_str());
+    hasher.update([0]);
+    hasher.update(source);
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, process};
+
+    fn specifier(url: &str) -> ModuleSpecifier {
+        ModuleSpecifier::parse(url).unwrap()
+    }
+
+    // transpiles to `code`, counting the calls.
+    fn transpile<'a>(
+        calls: &'a Cell<u32>,
+        code: &'a str,
+    ) -> impl FnOnce() -> anyhow::Result<TranspiledModule> + 'a {
+        move || {
+            calls.set(calls.get() + 1);
+            Ok(TranspiledModule {
+                code: code.to_string(),
+                source_map: None,
+            })
+        }
+    }
+
+    #[test]
+    fn the_key_covers_the_specifier_and_the_source() {
+        let main = specifier("file:///main.ts");
+
+        assert_eq!(content_hash(&main, "a"), content_hash(&main, "a"));
+        assert_ne!(content_hash(&main, "a"), content_hash(&main, "b"));
+        assert_ne!(
+            content_hash(&main, "a"),
+            content_hash(&specifier("file:///song.ts"), "a")
+        );
+    }
+
+    #[test]
+    fn changed_sources_are_transpiled_again() {
+        let cache = TranspileCache::new(None);
+        let main = specifier("file:///main.ts");
+        let calls = Cell::new(0);
+
+        cache
+            .get_or_transpile(&main, "a", transpile(&calls, "1"))
+            .unwrap();
+        let cached = cache
+            .get_or_transpile(&main, "a", transpile(&calls, "2"))
+            .unwrap();
+        assert_eq!((calls.get(), cached.code.as_str()), (1, "1"));
+
+        let changed = cache
+            .get_or_transpile(&main, "b", transpile(&calls, "3"))
+            .unwrap();
+        assert_eq!((calls.get(), changed.code.as_str()), (2, "3"));
+    }
+
+    #[test]
+    fn entries_on_disk_survive_a_new_cache() {
+        let directory =
+            std::env::temp_dir().join(format!("murmel-transpile-cache-{}", process::id()));
+        let main = specifier("file:///main.ts");
+        let calls = Cell::new(0);
+
+        TranspileCache::new(Some(directory.clone()))
+            .get_or_transpile(&main, "a", transpile(&calls, "1"))
+            .unwrap();
+        let cached = TranspileCache::new(Some(directory.clone()))
+            .get_or_transpile(&main, "a", transpile(&calls, "2"))
+            .unwrap();
+        assert_eq!((calls.get(), cached.code.as_str()), (1, "1"));
+
+        // unreadable entries are transpiled again.
+        let entry = directory.join(format!("{}.json", content_hash(&main, "a")));
+        fs::write(entry, "{").unwrap();
+        let transpiled = TranspileCache::new(Some(directory.clone()))
+            .get_or_transpile(&main, "a", transpile(&calls, "3"))
+            .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!((calls.get(), transpiled.code.as_str()), (2, "3"));
+    }
+}
@@ -2,11 +2,27 @@
 
 // From https://github.com/denoland/deno/blob/fda24b54e955c341c37ee29fbe59d9f7580e25e1/core/examples/ts_module_loader.rs
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use anyhow::bail;
+use anyhow::Context;
 use anyhow::Error;
+use deno_ast::swc::ast::DefaultDecl;
+use deno_ast::swc::ast::ExportSpecifier;
+use deno_ast::swc::ast::Expr;
+use deno_ast::swc::ast::ImportSpecifier;
+use deno_ast::swc::ast::ModuleDecl;
+use deno_ast::swc::ast::ModuleExportName;
+use deno_ast::swc::ast::ModuleItem;
+use deno_ast::EmitOptions;
 use deno_ast::MediaType;
 use deno_ast::ParseParams;
 use deno_ast::SourceTextInfo;
@@ -17,8 +33,94 @@ use deno_core::ModuleSource;
 use deno_core::ModuleSourceFuture;
 use deno_core::ModuleSpecifier;
 use deno_core::ModuleType;
+use deno_core::SourceMapGetter;
+use log::debug;
+use sourcemap::SourceMap;
 
-pub struct TypescriptModuleLoader;
+use crate::builtin_modules::builtin_module;
+use crate::builtin_modules::BUILTIN_SCHEME;
+use crate::import_map::is_bare_specifier;
+use crate::import_map::ImportMap;
+use crate::midi_file::MidiFile;
+use crate::snapshot::SnapshotModules;
+use crate::transpile_cache::TranspileCache;
+use crate::transpile_cache::TranspiledModule;
+
+/// Original sources and source maps of the loaded modules, so that positions
+/// in the transpiled code can be mapped back to the TypeScript source.
+#[derive(Clone, Default)]
+pub struct SourceMapStore {
+    modules: Rc<RefCell<HashMap<String, LoadedModule>>>,
+    // parsed when first used.
+    parsed_source_maps: Rc<RefCell<HashMap<String, Rc<SourceMap>>>>,
+}
+
+struct LoadedModule {
+    source: String,
+    source_map: Option<Vec<u8>>,
+}
+
+impl SourceMapStore {
+    pub fn insert(&self, specifier: &ModuleSpecifier, source: String, source_map: Option<String>) {
+        self.modules.borrow_mut().insert(
+            specifier.to_string(),
+            LoadedModule {
+                source,
+                source_map: source_map.map(String::into_bytes),
+            },
+        );
+        self.parsed_source_maps
+            .borrow_mut()
+            .remove(specifier.as_str());
+    }
+
+    /// Line and column in the original source of a position in the
+    /// transpiled code, all 1-based like in stack traces.
+    pub fn original_position(&self, file_name: &str, line: u32, column: u32) -> Option<(u32, u32)> {
+        let source_map = self.parsed_source_map(file_name)?;
+        let token = source_map.lookup_token(line.checked_sub(1)?, column.checked_sub(1)?)?;
+        Some((token.get_src_line() + 1, token.get_src_col() + 1))
+    }
+
+    fn parsed_source_map(&self, file_name: &str) -> Option<Rc<SourceMap>> {
+        if let Some(source_map) = self.parsed_source_maps.borrow().get(file_name) {
+            return Some(source_map.clone());
+        }
+
+        let modules = self.modules.borrow();
+        let source_map =
+            Rc::new(SourceMap::from_slice(modules.get(file_name)?.source_map.as_ref()?).ok()?);
+        self.parsed_source_maps
+            .borrow_mut()
+            .insert(file_name.to_string(), source_map.clone());
+        Some(source_map)
+    }
+
+    /// Line of the original source, `line_number` being 0-based.
+    pub fn source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        let modules = self.modules.borrow();
+        let module = modules.get(file_name)?;
+        module.source.lines().nth(line_number).map(str::to_string)
+    }
+}
+
+impl SourceMapGetter for SourceMapStore {
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.modules.borrow().get(file_name)?.source_map.clone()
+    }
+
+    fn get_source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        self.source_line(file_name, line_number)
+    }
+}
+
+pub struct TypescriptModuleLoader {
+    pub source_maps: SourceMapStore,
+    pub transpile_cache: TranspileCache,
+    pub import_map: Option<ImportMap>,
+    /// Modules evaluated into the runtime snapshot, served from there.
+    pub snapshot_modules: Option<Arc<SnapshotModules>>,
+}
 
 impl ModuleLoader for TypescriptModuleLoader {
     fn resolve(
@@ -27,7 +129,18 @@ impl ModuleLoader for TypescriptModuleLoader {
         referrer: &str,
         _is_main: bool,
     ) -> Result<ModuleSpecifier, Error> {
-        Ok(resolve_import(specifier, referrer)?)
+        if !is_bare_specifier(specifier) {
+            return Ok(resolve_import(specifier, referrer)?);
+        }
+
+        match &self.import_map {
+            Some(import_map) => import_map.resolve(specifier, referrer),
+            None => bail!(
+                "Could not resolve {:?} imported from {}, bare specifiers need an import map (murmel.json or import_map.json next to the main module)",
+                specifier,
+                referrer
+            ),
+        }
     }
 
     fn load(
@@ -37,15 +150,56 @@ impl ModuleLoader for TypescriptModuleLoader {
         _is_dyn_import: bool,
     ) -> Pin<Box<ModuleSourceFuture>> {
         let module_specifier = module_specifier.clone();
+        let source_maps = self.source_maps.clone();
+        let transpile_cache = self.transpile_cache.clone();
+        let snapshot_modules = self.snapshot_modules.clone();
         async move {
-            let mut path = module_specifier
-                .to_file_path()
-                .map_err(|_| anyhow!("Only file: URLs are supported."))?;
-
-            // a hack until https://github.com/microsoft/TypeScript/issues/37582
-            // moves somewhere.
-            if let None = path.extension() {
-                path = path.with_extension("ts")
+            // unless the module changed since the snapshot was made.
+            let facade = |source: &str| {
+                let facade = snapshot_modules
+                    .as_ref()
+                    .and_then(|modules| modules.facade(&module_specifier, source));
+                if facade.is_some() {
+                    debug!("Serving {} from the runtime snapshot", module_specifier);
+                }
+                facade
+            };
+
+            if module_specifier.scheme() == BUILTIN_SCHEME {
+                let source = builtin_module(&module_specifier)?;
+                let transpiled = transpile(
+                    &transpile_cache,
+                    &module_specifier,
+                    source,
+                    MediaType::TypeScript,
+                )?;
+                source_maps.insert(&module_specifier, source.to_string(), transpiled.source_map);
+                let code = facade(source).unwrap_or(transpiled.code);
+
+                return Ok(ModuleSource {
+                    code: code.into_bytes().into_boxed_slice(),
+                    module_type: ModuleType::JavaScript,
+                    module_url_specified: module_specifier.to_string(),
+                    module_url_found: module_specifier.to_string(),
+                });
+            }
+
+            let path = module_path(&module_specifier)?;
+
+            if is_midi_file(&path) {
+                let bytes = std::fs::read(&path)?;
+                let json = MidiFile::parse(&bytes)
+                    .and_then(|midi_file| midi_file.to_json())
+                    .with_context(|| {
+                        format!("Could not load MIDI file {}", path.to_string_lossy())
+                    })?;
+
+                return Ok(ModuleSource {
+                    code: json.into_bytes().into_boxed_slice(),
+                    module_type: ModuleType::Json,
+                    module_url_specified: module_specifier.to_string(),
+                    module_url_found: module_specifier.to_string(),
+                });
             }
 
             let media_type = MediaType::from(&path);
@@ -65,21 +219,22 @@ impl ModuleLoader for TypescriptModuleLoader {
                 _ => bail!("Unknown extension {:?}", path.extension()),
             };
 
-            let code = std::fs::read_to_string(&path)?;
-            let code = if should_transpile {
-                let parsed = deno_ast::parse_module(ParseParams {
-                    specifier: module_specifier.to_string(),
-                    text_info: SourceTextInfo::from_string(code),
-                    media_type,
-                    capture_tokens: false,
-                    scope_analysis: false,
-                    maybe_syntax: None,
-                })?;
-
-                parsed.transpile(&Default::default())?.text
+            let source = std::fs::read_to_string(&path)?;
+            let (code, source_map) = if should_transpile {
+                let transpiled =
+                    transpile(&transpile_cache, &module_specifier, &source, media_type)?;
+
+                (transpiled.code, transpiled.source_map)
             } else {
-                code
+                (source.clone(), None)
             };
+
+            let code = match module_type {
+                ModuleType::JavaScript => facade(&source).unwrap_or(code),
+                ModuleType::Json => code,
+            };
+            source_maps.insert(&module_specifier, source, source_map);
+
             let module = ModuleSource {
                 code: code.into_bytes().into_boxed_slice(),
                 module_type,
@@ -91,3 +246,337 @@ impl ModuleLoader for TypescriptModuleLoader {
         .boxed_local()
     }
 }
+
+// the file of a resolved module.
+fn module_path(module_specifier: &ModuleSpecifier) -> Result<PathBuf, Error> {
+    let path = module_specifier
+        .to_file_path()
+        .map_err(|_| anyhow!("Only file: URLs are supported."))?;
+
+    // a hack until https://github.com/microsoft/TypeScript/issues/37582
+    // moves somewhere.
+    if path.extension().is_none() {
+        return Ok(path.with_extension("ts"));
+    }
+
+    Ok(path)
+}
+
+pub fn transpile(
+    transpile_cache: &TranspileCache,
+    specifier: &ModuleSpecifier,
+    source: &str,
+    media_type: MediaType,
+) -> Result<TranspiledModule, Error> {
+    transpile_cache.get_or_transpile(specifier, source, || {
+        let parsed = deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            text_info: SourceTextInfo::from_string(source.to_string()),
+            media_type,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })?;
+
+        let transpiled = parsed.transpile(&EmitOptions {
+            inline_source_map: false,
+            source_map: true,
+            ..Default::default()
+        })?;
+
+        Ok(TranspiledModule {
+            code: transpiled.text,
+            source_map: transpiled.source_map,
+        })
+    })
+}
+
+// MIDI files are served as JSON modules, so they need to be imported with
+// `assert { type: 'json' }`.
+fn is_midi_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_lowercase();
+            ext == "mid" || ext == "midi"
+        }
+        None => false,
+    }
+}
+impl TypescriptModuleLoader {
+    /// Statically checks that the module at `path` has a default export that
+    /// can be an iterator. Catches the common mistake of exporting a
+    /// generator function instead of the iterator it returns, before anything
+    /// is evaluated.
+    ///
+    /// A default export re-exported or imported from another local module,
+    /// e.g. `export { default } from './song'`, is followed to the module
+    /// that defines it, resolved like the import itself. Builtin modules and
+    /// specifiers that don't resolve are left to the check after loading.
+    pub fn check_default_export(&self, path: &Path) -> Result<(), Error> {
+        let mut visited = HashSet::new();
+        let mut path = path.to_path_buf();
+
+        loop {
+            if !visited.insert(path.clone()) {
+                bail!(
+                    "{} re-exports its own default export",
+                    path.to_string_lossy()
+                );
+            }
+
+            match default_export_of(&path)? {
+                Some(specifier) => match self.local_module_path(&path, &specifier) {
+                    Some(next) => path = next,
+                    None => return Ok(()),
+                },
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // the file `specifier` imported from `referrer` is loaded from, `None`
+    // for builtin modules and URLs.
+    fn local_module_path(&self, referrer: &Path, specifier: &str) -> Option<PathBuf> {
+        let referrer = ModuleSpecifier::from_file_path(referrer).ok()?;
+        let resolved = self.resolve(specifier, referrer.as_str(), false).ok()?;
+        if resolved.scheme() != "file" {
+            return None;
+        }
+
+        module_path(&resolved).ok()
+    }
+}
+
+// checks the default export of a single module, returning the specifier it
+// comes from when it is another module's default export.
+fn default_export_of(path: &Path) -> Result<Option<String>, Error> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.to_string_lossy()))?;
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: path.to_string_lossy().to_string(),
+        text_info: SourceTextInfo::from_string(source),
+        media_type: MediaType::from(path),
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })?;
+
+    let not_iterator = |what: &str| {
+        anyhow!(
+            "Default export of {} is {}, not an iterator. Export the iterator it returns instead, e.g. `export default generator()`",
+            path.to_string_lossy(),
+            what
+        )
+    };
+
+    let is_default = |name: &ModuleExportName| matches!(name, ModuleExportName::Ident(ident) if &*ident.sym == "default");
+
+    // `import song from './song.ts'`, to follow `export default song`.
+    let default_import = |local: &str| {
+        parsed.module().body.iter().find_map(|item| match item {
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => import
+                .specifiers
+                .iter()
+                .find_map(|specifier| match specifier {
+                    ImportSpecifier::Default(default) if &*default.local.sym == local => {
+                        Some(import.src.value.to_string())
+                    }
+                    _ => None,
+                }),
+            _ => None,
+        })
+    };
+
+    for item in parsed.module().body.iter() {
+        let decl = match item {
+            ModuleItem::ModuleDecl(decl) => decl,
+            ModuleItem::Stmt(_) => continue,
+        };
+
+        match decl {
+            ModuleDecl::ExportDefaultDecl(export) => {
+                return match &export.decl {
+                    DefaultDecl::Fn(f) if f.function.is_generator => {
+                        Err(not_iterator("a generator function"))
+                    }
+                    DefaultDecl::Fn(_) => Err(not_iterator("a function")),
+                    DefaultDecl::Class(_) => Err(not_iterator("a class")),
+                    DefaultDecl::TsInterfaceDecl(_) => Err(not_iterator("an interface")),
+                };
+            }
+
+            ModuleDecl::ExportDefaultExpr(export) => {
+                return match &*export.expr {
+                    Expr::Fn(f) if f.function.is_generator => {
+                        Err(not_iterator("a generator function"))
+                    }
+                    Expr::Fn(_) | Expr::Arrow(_) => Err(not_iterator("a function")),
+                    Expr::Ident(ident) => Ok(default_import(&ident.sym)),
+                    _ => Ok(None),
+                };
+            }
+
+            ModuleDecl::ExportNamed(export) => {
+                let orig = export
+                    .specifiers
+                    .iter()
+                    .find_map(|specifier| match specifier {
+                        ExportSpecifier::Named(named)
+                            if is_default(named.exported.as_ref().unwrap_or(&named.orig)) =>
+                        {
+                            Some(&named.orig)
+                        }
+                        _ => None,
+                    });
+
+                match (orig, &export.src) {
+                    // `export { default } from './song.ts'`
+                    (Some(orig), Some(src)) => {
+                        return Ok(is_default(orig).then(|| src.value.to_string()))
+                    }
+                    // `export { song as default }`
+                    (Some(ModuleExportName::Ident(ident)), None) => {
+                        return Ok(default_import(&ident.sym))
+                    }
+                    (Some(_), None) => return Ok(None),
+                    (None, _) => (),
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    bail!("{} has no default export", path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    fn check(name: &str, modules: &[(&str, &str)]) -> Result<(), Error> {
+        let directory = std::env::temp_dir().join(format!("murmel-{}-{}", name, process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for (file, source) in modules {
+            fs::write(directory.join(file), source).unwrap();
+        }
+
+        let loader = TypescriptModuleLoader {
+            source_maps: Default::default(),
+            transpile_cache: Default::default(),
+            import_map: None,
+            snapshot_modules: None,
+        };
+        let result = loader.check_default_export(&directory.join(modules[0].0));
+        fs::remove_dir_all(&directory).unwrap();
+        result
+    }
+
+    #[test]
+    fn rejects_a_default_exported_generator_function() {
+        let error = check(
+            "generator",
+            &[("main.ts", "export default function* () {}")],
+        );
+
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("a generator function"));
+        assert!(check(
+            "iterator",
+            &[("main.ts", "export default (function* () {})()")]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn follows_default_exports_into_local_modules() {
+        let song = ("song.ts", "export default function* song() {}");
+
+        assert!(check(
+            "reexport",
+            &[("main.ts", "export { default } from './song.ts'"), song]
+        )
+        .is_err());
+        assert!(check(
+            "import",
+            &[
+                (
+                    "main.ts",
+                    "import song from './song.ts'\nexport default song"
+                ),
+                song
+            ]
+        )
+        .is_err());
+        assert!(check(
+            "named",
+            &[
+                (
+                    "main.ts",
+                    "import song from './song.ts'\nexport { song as default }"
+                ),
+                song
+            ]
+        )
+        .is_err());
+        assert!(check(
+            "extensionless",
+            &[("main.ts", "export { default } from './song'"), song]
+        )
+        .unwrap_err()
+        .to_string()
+        .contains("a generator function"));
+        assert!(check(
+            "builtin",
+            &[("main.ts", "export { default } from 'murmel:std'")]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn positions_map_back_to_the_source() {
+        let specifier = ModuleSpecifier::parse("file:///main.ts").unwrap();
+        let source = "interface Note {\n  pitch: number\n}\n\nconsole.log('hi')\n";
+        let transpiled = transpile(
+            &TranspileCache::default(),
+            &specifier,
+            source,
+            MediaType::TypeScript,
+        )
+        .unwrap();
+        let line = transpiled
+            .code
+            .lines()
+            .position(|line| line.starts_with("console.log"))
+            .unwrap() as u32
+            + 1;
+
+        let source_maps = SourceMapStore::default();
+        source_maps.insert(&specifier, source.to_string(), transpiled.source_map);
+
+        assert_eq!(
+            source_maps.original_position(specifier.as_str(), line, 1),
+            Some((5, 1))
+        );
+        assert_eq!(
+            source_maps.original_position("file:///other.ts", 1, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_circular_default_exports() {
+        let error = check(
+            "cycle",
+            &[("main.ts", "export { default } from './main.ts'")],
+        );
+
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("re-exports its own"));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{event::TICKS_PER_BEAT, event_schema::EVENT_SCHEMA};
+use std::{fmt::Write, fs, path::Path};
+
+const RUNTIME_DECLARATIONS: &str = include_str!("js/murmel.d.ts");
+
+/// TypeScript declarations for `murmel:std` and the runtime globals. The
+/// `Event` union and the constants are generated from the Rust definitions,
+/// so they can't drift from what the player accepts.
+pub fn type_declarations() -> String {
+    let mut declarations = String::new();
+
+    let _ = writeln!(
+        declarations,
+        "// Generated by `murmel types` (murmel {}), do not edit.\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    declarations.push_str("declare module 'murmel:std' {\n");
+    let _ = writeln!(
+        declarations,
+        "    export const TICKS_PER_BEAT: {}\n",
+        TICKS_PER_BEAT
+    );
+
+    declarations.push_str("    export type Event =\n");
+    for (name, fields) in EVENT_SCHEMA {
+        declarations.push_str("        | {\n");
+        let _ = writeln!(declarations, "              type: '{}'", name);
+        for (field, kind) in fields.iter() {
+            let _ = writeln!(declarations, "              // {}", kind.description());
+            let _ = writeln!(
+                declarations,
+                "              {}: {}",
+                field,
+                kind.typescript_type()
+            );
+        }
+        declarations.push_str("          }\n");
+    }
+
+    for line in RUNTIME_DECLARATIONS.lines() {
+        declarations.push('\n');
+        if !line.is_empty() {
+            let _ = write!(declarations, "    {}", line);
+        }
+    }
+
+    declarations.push_str("\n}\n");
+    declarations
+}
+
+/// Implements `murmel types [<path>]`, printing to stdout without a path.
+pub fn write_type_declarations(path: Option<&Path>) -> anyhow::Result<()> {
+    let declarations = type_declarations();
+
+    match path {
+        Some(path) => {
+            fs::write(path, declarations)?;
+            eprintln!("Wrote type declarations to {}", path.display());
+        }
+        None => print!("{}", declarations),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{murmel_extension, OpsContext};
+    use deno_ast::{
+        swc::ast::{
+            Callee, Decl, Expr, Lit, MemberProp, ModuleItem, ObjectLit, Pat, Prop, PropName,
+            PropOrSpread, Stmt, TsModuleName, TsNamespaceBody, TsType, TsTypeElement,
+        },
+        MediaType, ParseParams, ParsedSource, SourceRangedForSpanned, SourceTextInfo,
+    };
+
+    fn parse(specifier: &str, source: &str, media_type: MediaType) -> ParsedSource {
+        deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            text_info: SourceTextInfo::from_string(source.to_string()),
+            media_type,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })
+        .unwrap()
+    }
+
+    // `(global, property, source of its value)` for the globals a runtime
+    // file sets up, e.g. `const murmel = { ... }` or `window.console = { ... }`.
+    fn runtime_properties(specifier: &str, source: &str) -> Vec<(String, String, String)> {
+        let parsed = parse(specifier, source, MediaType::JavaScript);
+        let text_info = parsed.text_info();
+        let mut properties = vec![];
+
+        let object_properties = |global: &str, object: &ObjectLit| {
+            let mut properties = vec![];
+            for prop in &object.props {
+                if let PropOrSpread::Prop(prop) = prop {
+                    if let Prop::KeyValue(kv) = &**prop {
+                        if let PropName::Ident(key) = &kv.key {
+                            properties.push((
+                                global.to_string(),
+                                key.sym.to_string(),
+                                kv.value.text_fast(&text_info).to_string(),
+                            ));
+                        }
+                    }
+                }
+            }
+            properties
+        };
+
+        // `;((window) => { ... })(globalThis)`
+        let stmts = parsed.module().body.iter().find_map(|item| match item {
+            ModuleItem::Stmt(Stmt::Expr(stmt)) => match &*stmt.expr {
+                Expr::Call(call) => match &call.callee {
+                    Callee::Expr(callee) => match &**callee {
+                        Expr::Paren(paren) => match &*paren.expr {
+                            Expr::Arrow(arrow) => arrow.body.as_block_stmt().map(|b| &b.stmts),
+                            _ => None,
+                        },
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        });
+
+        for stmt in stmts.unwrap_or_else(|| panic!("{} is not wrapped in a function", specifier)) {
+            match stmt {
+                // `const murmel = { ... }`
+                Stmt::Decl(Decl::Var(var)) => {
+                    for decl in &var.decls {
+                        if let (Pat::Ident(name), Some(Expr::Object(object))) =
+                            (&decl.name, decl.init.as_deref())
+                        {
+                            properties.extend(object_properties(&name.id.sym, object));
+                        }
+                    }
+                }
+                Stmt::Expr(stmt) => match &*stmt.expr {
+                    // `window.console = { ... }`
+                    Expr::Assign(assign) => {
+                        if let (Some(Expr::Member(member)), Expr::Object(object)) =
+                            (assign.left.as_expr(), &*assign.right)
+                        {
+                            if let MemberProp::Ident(global) = &member.prop {
+                                properties.extend(object_properties(&global.sym, object));
+                            }
+                        }
+                    }
+                    // `Object.defineProperty(murmel, 'transport', { ... })`
+                    Expr::Call(call) => {
+                        if let [global, name, descriptor] = &call.args[..] {
+                            if let (Expr::Ident(global), Expr::Lit(Lit::Str(name))) =
+                                (&*global.expr, &*name.expr)
+                            {
+                                properties.push((
+                                    global.sym.to_string(),
+                                    name.value.to_string(),
+                                    descriptor.expr.text_fast(&text_info).to_string(),
+                                ));
+                            }
+                        }
+                    }
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+
+        properties
+    }
+
+    // `(global, member)` for `const murmel: { ... }` and the like in
+    // `global { ... }`.
+    fn declared_properties() -> Vec<(String, String)> {
+        let parsed = parse("file:///murmel.d.ts", RUNTIME_DECLARATIONS, MediaType::Dts);
+        let mut members = vec![];
+
+        for item in parsed.module().body.iter() {
+            let block = match item {
+                ModuleItem::Stmt(Stmt::Decl(Decl::TsModule(module))) => {
+                    match (&module.id, &module.body) {
+                        (TsModuleName::Ident(id), Some(TsNamespaceBody::TsModuleBlock(block)))
+                            if &*id.sym == "global" =>
+                        {
+                            block
+                        }
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            for item in &block.body {
+                let var = match item {
+                    ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) => var,
+                    _ => continue,
+                };
+                for decl in &var.decls {
+                    let (global, literal) = match &decl.name {
+                        Pat::Ident(ident) => {
+                            match ident.type_ann.as_ref().map(|ann| &*ann.type_ann) {
+                                Some(TsType::TsTypeLit(literal)) => {
+                                    (ident.id.sym.to_string(), literal)
+                                }
+                                _ => continue,
+                            }
+                        }
+                        _ => continue,
+                    };
+                    for member in &literal.members {
+                        let key = match member {
+                            TsTypeElement::TsPropertySignature(property) => &property.key,
+                            TsTypeElement::TsMethodSignature(method) => &method.key,
+                            _ => continue,
+                        };
+                        if let Expr::Ident(ident) = &**key {
+                            members.push((global.clone(), ident.sym.to_string()));
+                        }
+                    }
+                }
+            }
+        }
+
+        members
+    }
+
+    #[test]
+    fn declarations_are_valid_typescript() {
+        parse("file:///murmel.d.ts", &type_declarations(), MediaType::Dts);
+    }
+
+    #[test]
+    fn every_op_is_declared() {
+        let mut extension = murmel_extension(OpsContext {
+            params: Default::default(),
+            midi_input: Default::default(),
+            random_seed: 0,
+            transport_status: Default::default(),
+        });
+        let runtime_files = extension.init_js().to_vec();
+        let ops = extension.init_ops().unwrap();
+
+        let properties: Vec<_> = runtime_files
+            .iter()
+            .flat_map(|(specifier, source)| runtime_properties(specifier, source))
+            .collect();
+        let declared = declared_properties();
+
+        for (global, name, _) in &properties {
+            assert!(
+                declared.contains(&(global.clone(), name.clone())),
+                "{}.{} is not declared in murmel.d.ts",
+                global,
+                name
+            );
+        }
+
+        for op in ops {
+            assert!(
+                runtime_files
+                    .iter()
+                    .any(|(_, source)| source.contains(op.name)),
+                "{} is not used by the runtime files",
+                op.name
+            );
+        }
+    }
+}